@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ncm_core::audio::Audio;
+use std::io::Read;
+
+const KEY: &[u8] =
+    b"E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb";
+
+fn bench_decrypt(c: &mut Criterion) {
+    let data = vec![0x5A; 16 << 20];
+
+    let mut group = c.benchmark_group("decrypt");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    // The byte by byte loop `Audio::read` used before, kept as the baseline.
+    group.bench_function(BenchmarkId::new("cycle", data.len()), |b| {
+        let key_stream: Vec<u8> = (0..=255).collect();
+        let mut buf = data.clone();
+        b.iter(|| {
+            let mut key_iter = key_stream.iter().cycle();
+            buf.chunks_mut(0x8000).for_each(|chunk| {
+                chunk.iter_mut().zip(&mut key_iter).for_each(|(byte, x)| *byte ^= x);
+            });
+        });
+    });
+
+    group.bench_function(BenchmarkId::new("audio", data.len()), |b| {
+        let mut buf = vec![0; 0x8000];
+        b.iter(|| {
+            let mut audio = Audio::try_new(&*data, KEY).unwrap();
+            while audio.read(&mut buf).unwrap() > 0 {}
+        });
+    });
+
+    #[cfg(feature = "rayon")]
+    group.bench_function(BenchmarkId::new("parallel", data.len()), |b| {
+        let mut buf = data.clone();
+        b.iter(|| ncm_core::key_stream::decrypt_parallel(&mut buf, KEY));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_decrypt);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ncm_core::{decoder::Decoder, options::DecodeOptions};
+use std::io::Read;
+
+fuzz_target!(|data: &[u8]| {
+    for verify_crc in [false, true] {
+        let options = DecodeOptions { verify_crc, ..Default::default() };
+        if let Ok(mut decoder) = Decoder::decode_with(data, options) {
+            let _ = decoder.audio.read_to_end(&mut vec![]);
+        }
+    }
+});
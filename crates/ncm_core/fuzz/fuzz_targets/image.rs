@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ncm_core::image::Image;
+
+fuzz_target!(|data: &[u8]| {
+    let image = Image::from(data.to_vec());
+    let _ = (image.ext(), image.mime_type());
+});
@@ -0,0 +1,173 @@
+use crate::{
+    audio::{Cipher, State, Type as AudioType, MIN_AUDIO_LEN, SNIFF_LEN},
+    decoder::{Collector, Parts},
+    error::{Error, Result},
+    header::Header,
+    image::Image,
+    layout::Layout,
+    meta::MetaKind,
+    options::DecodeOptions,
+    parser::Truncation,
+};
+use std::{
+    fmt::Debug,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// The async counterpart of [`crate::decoder::Decoder`], for tokio readers.
+#[derive(Debug)]
+pub struct AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub header: Header,
+    pub key: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
+    pub meta: Vec<u8>,
+    pub image: Option<Image>,
+    pub audio: AsyncAudio<R>,
+    /// Set if the input ended before the audio, only with [`DecodeOptions::lenient`].
+    pub truncated: Option<Truncation>,
+    pub layout: Layout,
+}
+
+impl<R> AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub async fn decode(input: R) -> Result<Self> {
+        Self::decode_with(input, DecodeOptions::default()).await
+    }
+
+    pub async fn decode_with(mut input: R, options: DecodeOptions) -> Result<Self> {
+        let min_len = if options.lenient { 0 } else { MIN_AUDIO_LEN };
+        let mut collector = Collector::new(options);
+
+        let mut buffer = vec![];
+        while !collector.is_done() {
+            buffer.resize(collector.wanted(), 0);
+            let size =
+                input.read(&mut buffer).await.map_err(|err| Error::io(collector.offset(), err))?;
+            collector.feed(&buffer[..size])?;
+        }
+
+        let Parts {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image,
+            audio_offset,
+            truncated,
+            layout,
+            ..
+        } = collector.finish();
+        let audio = AsyncAudio::read_header(input, Cipher::ncm(&key), min_len)
+            .await
+            .map_err(|err| Error::io(audio_offset, err))?
+            .with_offset(audio_offset);
+
+        Ok(Self { header, key, comment, meta_kind, meta, image, audio, truncated, layout })
+    }
+
+    pub fn audio_type(&self) -> AudioType {
+        self.audio.r#type()
+    }
+
+    pub fn ext(&self) -> String {
+        self.audio.ext()
+    }
+
+    /// The offset of the audio data from the start of the ncm file.
+    pub fn audio_offset(&self) -> u64 {
+        self.audio.offset()
+    }
+}
+
+/// The async counterpart of [`crate::audio::Audio`].
+pub struct AsyncAudio<R>
+where
+    R: AsyncRead + Unpin,
+{
+    state: State,
+    reader: R,
+}
+
+impl<R> AsyncAudio<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub async fn try_new(input: R, key: &[u8]) -> io::Result<Self> {
+        Self::read_header(input, Cipher::ncm(key), MIN_AUDIO_LEN).await
+    }
+
+    /// The audio of a QMCv1 file, see [`crate::qmc::decode_async`].
+    pub(crate) async fn try_new_qmc(input: R, min_len: usize) -> io::Result<Self> {
+        Self::read_header(input, Cipher::Qmc, min_len).await
+    }
+
+    async fn read_header(mut input: R, cipher: Cipher, min_len: usize) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        (&mut input).take(SNIFF_LEN as u64).read_to_end(&mut header).await?;
+        if header.len() < min_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Self { state: State::new(cipher, header), reader: input })
+    }
+
+    fn with_offset(mut self, offset: u64) -> Self {
+        self.state.set_offset(offset);
+        self
+    }
+
+    pub fn r#type(&self) -> AudioType {
+        self.state.r#type()
+    }
+
+    pub fn ext(&self) -> String {
+        self.r#type().to_string()
+    }
+
+    /// The offset of the audio data from the start of the ncm file.
+    pub fn offset(&self) -> u64 {
+        self.state.offset()
+    }
+}
+
+impl<R> AsyncRead for AsyncAudio<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let start = buf.filled().len();
+
+        match this.state.read_header(buf.initialize_unfilled()) {
+            Some(size) => buf.advance(size),
+            None => ready!(Pin::new(&mut this.reader).poll_read(cx, buf))?,
+        }
+
+        this.state.decrypt(&mut buf.filled_mut()[start..]);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R> Debug for AsyncAudio<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AsyncAudio").field(&format!("{}", self.r#type())).finish()
+    }
+}
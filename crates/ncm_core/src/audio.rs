@@ -1,27 +1,120 @@
-use crate::ncm_rc4::NcmRc4;
-use anyhow::Result;
-use std::{
-    fmt::{Debug, Display},
-    io::{Chain, Cursor, Read},
-    iter::Cycle,
+use crate::{
+    error::{Error, Result},
+    ncm_rc4::NcmRc4,
+    qmc,
+    source::{self, ByteSource, SourceError},
 };
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{Debug, Display};
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+/// How many bytes from the start of the audio are read ahead to sniff its [`Type`].
+pub const SNIFF_LEN: usize = 64;
+
+/// The fewest audio bytes a decoder accepts, the 12 bytes the sniffing always relied on.
+pub(crate) const MIN_AUDIO_LEN: usize = 12;
+
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_SIZE: usize = 0x10_0000;
 
-#[derive(Debug, Clone, Copy)]
+/// The container of the audio, sniffed from its first bytes.
+///
+/// More variants may be added in minor releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Type {
     Flac,
     Mp3,
+    Aac,
     M4a,
+    M4b,
+    Mp4,
+    /// Vorbis, or any other codec, in an Ogg container.
     Ogg,
+    Opus,
+    Wav,
+    Ape,
+    Dsf,
+    Dff,
     Unknown,
 }
 
+impl Type {
+    /// Guesses the container from the first bytes of the decrypted audio, up to [`SNIFF_LEN`] are used.
+    pub fn sniff(data: &[u8]) -> Self {
+        let at =
+            |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+
+        match data {
+            _ if at(0, b"fLaC") => Type::Flac,
+            _ if at(0, b"OggS") => {
+                // the codec header follows the 27 byte page header and its segment table
+                let codec = 27 + data.get(26).map_or(0, |&len| len as usize);
+                if at(codec, b"OpusHead") {
+                    Type::Opus
+                } else {
+                    Type::Ogg
+                }
+            }
+            _ if at(0, b"ID3") => Type::Mp3,
+            // ADTS, sync word with layer 0
+            [0xFF, byte, ..] if byte & 0xF6 == 0xF0 => Type::Aac,
+            // MPEG audio frame, sync word with layer III
+            [0xFF, byte, ..] if byte & 0xE6 == 0xE2 => Type::Mp3,
+            _ if at(4, b"ftypM4A ") => Type::M4a,
+            _ if at(4, b"ftypM4B ") => Type::M4b,
+            _ if at(4, b"ftypisom") || at(4, b"ftypmp42") || at(4, b"ftypmp41") => Type::Mp4,
+            _ if at(0, b"RIFF") && at(8, b"WAVE") => Type::Wav,
+            _ if at(0, b"MAC ") => Type::Ape,
+            _ if at(0, b"DSD ") => Type::Dsf,
+            _ if at(0, b"FRM8") && at(12, b"DSD ") => Type::Dff,
+            _ => Type::Unknown,
+        }
+    }
+
+    /// The type with the extension `ext`, case insensitive, e.g. the `format` in the meta.
+    pub fn from_ext(ext: &str) -> Self {
+        [
+            Type::Flac,
+            Type::Mp3,
+            Type::Aac,
+            Type::M4a,
+            Type::M4b,
+            Type::Mp4,
+            Type::Ogg,
+            Type::Opus,
+            Type::Wav,
+            Type::Ape,
+            Type::Dsf,
+            Type::Dff,
+        ]
+        .into_iter()
+        .find(|r#type| r#type.to_string().eq_ignore_ascii_case(ext))
+        .unwrap_or(Type::Unknown)
+    }
+}
+
 impl Display for Type {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let ext = match &self {
             Type::Flac => "flac",
             Type::Mp3 => "mp3",
+            Type::Aac => "aac",
             Type::M4a => "m4a",
+            Type::M4b => "m4b",
+            Type::Mp4 => "mp4",
             Type::Ogg => "ogg",
+            Type::Opus => "opus",
+            Type::Wav => "wav",
+            Type::Ape => "ape",
+            Type::Dsf => "dsf",
+            Type::Dff => "dff",
             Type::Unknown => "audio",
         };
 
@@ -31,83 +124,339 @@ impl Display for Type {
 
 impl From<[u8; 12]> for Type {
     fn from(value: [u8; 12]) -> Self {
-        match &value[..4] {
-            b"fLaC" => Type::Flac,
-            b"OggS" => Type::Ogg,
-            [0xFF, 0xFB, ..] => Type::Mp3,
-            [b'I', b'D', b'3', ..] => Type::Mp3,
-            _ => {
-                if &value[4..12] == b"ftypM4A " {
-                    Type::M4a
-                } else {
-                    Type::Unknown
-                }
+        Type::sniff(&value)
+    }
+}
+
+/// How the audio is encrypted.
+#[derive(Debug, Clone)]
+pub(crate) enum Cipher {
+    Ncm(Box<NcmRc4>),
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    Qmc,
+}
+
+impl Cipher {
+    pub(crate) fn ncm(key: &[u8]) -> Self {
+        Cipher::Ncm(Box::new(NcmRc4::new(key)))
+    }
+
+    /// XORs `buf` with the key stream or mask, `position` being the offset of `buf` in the audio.
+    pub(crate) fn apply(&self, position: u64, buf: &mut [u8]) {
+        match self {
+            Cipher::Ncm(rc4) => rc4.apply(position, buf),
+            Cipher::Qmc => qmc::apply(position, buf),
+        }
+    }
+
+    /// [`Cipher::apply`] on chunks of `buf` spread over the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn apply_parallel(&self, position: u64, buf: &mut [u8]) {
+        use rayon::prelude::*;
+
+        buf.par_chunks_mut(PARALLEL_CHUNK_SIZE).enumerate().for_each(|(i, chunk)| {
+            self.apply(position + (i * PARALLEL_CHUNK_SIZE) as u64, chunk);
+        });
+    }
+}
+
+/// The reader-independent half of [`Audio`], shared with the async audio: the read-ahead header
+/// that the type was sniffed from, and the position the cipher is applied at.
+pub(crate) struct State {
+    r#type: Type,
+    cipher: Cipher,
+    offset: u64,
+    position: u64,
+    header: Vec<u8>,
+}
+
+impl State {
+    pub(crate) fn new(cipher: Cipher, header: Vec<u8>) -> Self {
+        let r#type = {
+            let mut buf = header.clone();
+            cipher.apply(0, &mut buf);
+            Type::sniff(&buf)
+        };
+
+        Self { r#type, cipher, offset: 0, position: 0, header }
+    }
+
+    /// Copies the rest of the read-ahead header into `buf`, or `None` once it is used up.
+    pub(crate) fn read_header(&self, buf: &mut [u8]) -> Option<usize> {
+        match self.header.get(self.position as usize..) {
+            Some(header) if !header.is_empty() => {
+                let size = header.len().min(buf.len());
+                buf[..size].copy_from_slice(&header[..size]);
+                Some(size)
             }
+            _ => None,
         }
     }
+
+    /// Decrypts the `buf` just read in place and moves past it.
+    pub(crate) fn decrypt(&mut self, buf: &mut [u8]) {
+        self.cipher.apply(self.position, buf);
+        self.position += buf.len() as u64;
+    }
+
+    pub(crate) fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub(crate) fn r#type(&self) -> Type {
+        self.r#type
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
 }
 
-type Rc4Iter = Cycle<std::array::IntoIter<u8, 256_usize>>;
+pub struct Audio<R> {
+    state: State,
+    reader: R,
+}
 
-pub struct Audio<R>
+#[cfg(feature = "std")]
+impl<R> Audio<R>
 where
     R: Read,
 {
-    r#type: Type,
-    rc4_iter: Rc4Iter,
-    reader: Chain<Cursor<[u8; 12]>, R>,
+    /// Reads the rest of the audio into `buf` and decrypts it in one go on the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn read_to_end_parallel(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        let state = &mut self.state;
+        let start = buf.len();
+        if let Some(header) = state.header.get(state.position as usize..) {
+            buf.extend_from_slice(header);
+        }
+        self.reader.read_to_end(buf)?;
+
+        let chunk = &mut buf[start..];
+        state.cipher.apply_parallel(state.position, chunk);
+        state.position += chunk.len() as u64;
+
+        Ok(chunk.len())
+    }
+
+    pub fn try_new(input: R, key: &[u8]) -> std::io::Result<Self> {
+        Self::try_new_with(input, key, MIN_AUDIO_LEN)
+    }
+
+    /// [`Audio::try_new`], failing if the audio is shorter than `min_len`.
+    pub(crate) fn try_new_with(input: R, key: &[u8], min_len: usize) -> std::io::Result<Self> {
+        Self::read_header(input, Cipher::ncm(key), min_len)
+    }
+
+    /// The audio of a QMCv1 file, see [`crate::qmc::decode`].
+    pub(crate) fn try_new_qmc(input: R, min_len: usize) -> std::io::Result<Self> {
+        Self::read_header(input, Cipher::Qmc, min_len)
+    }
+
+    fn read_header(mut input: R, cipher: Cipher, min_len: usize) -> std::io::Result<Self> {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        (&mut input).take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+        if header.len() < min_len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Self { state: State::new(cipher, header), reader: input })
+    }
 }
 
-impl<R> Audio<R>
+impl<S> Audio<S>
 where
-    R: Read,
+    S: ByteSource,
 {
-    pub fn try_new(mut input: R, key: &[u8]) -> Result<Self> {
-        let rc4_iter = NcmRc4::new(key).into_iter().cycle();
+    pub fn try_from_source(input: S, key: &[u8]) -> Result<Self> {
+        Self::from_source_at(input, key, 0, MIN_AUDIO_LEN)
+    }
 
-        let mut buf = [0; 12];
-        input.read_exact(&mut buf)?;
+    pub(crate) fn from_source_at(
+        mut input: S,
+        key: &[u8],
+        offset: u64,
+        min_len: usize,
+    ) -> Result<Self> {
+        let mut header = vec![0; SNIFF_LEN];
+        let len = source::read_up_to(&mut input, &mut header, offset)?;
+        if len < min_len {
+            return Err(Error::UnexpectedEof { offset });
+        }
+        header.truncate(len);
 
-        let reader = Cursor::new(buf).chain(input);
+        let state = State::new(Cipher::ncm(key), header);
+        Ok(Self { state, reader: input }.with_offset(offset))
+    }
 
-        let r#type = {
-            let mut tmp_iter = rc4_iter.clone();
-            Self::decrypt(&mut tmp_iter, &mut buf);
-            buf.into()
-        };
+    /// Reads decrypted audio from a [`ByteSource`], the counterpart of [`std::io::Read::read`].
+    pub fn read_source(&mut self, buf: &mut [u8]) -> core::result::Result<usize, SourceError> {
+        self.read_with(buf, |reader, buf| reader.read(buf))
+    }
+}
 
-        Ok(Self { r#type, rc4_iter, reader })
+impl<R> Audio<R> {
+    pub(crate) fn with_offset(mut self, offset: u64) -> Self {
+        self.state.set_offset(offset);
+        self
     }
 
     pub fn r#type(&self) -> Type {
-        self.r#type
+        self.state.r#type()
     }
 
     pub fn ext(&self) -> String {
-        self.r#type.to_string()
+        self.r#type().to_string()
     }
 
-    fn decrypt(rc4_iter: &mut Rc4Iter, buf: &mut [u8]) {
-        buf.iter_mut().zip(rc4_iter).for_each(|(byte, x)| *byte ^= x);
+    /// The offset of the audio data from the start of the ncm file.
+    pub fn offset(&self) -> u64 {
+        self.state.offset()
+    }
+
+    fn read_with<E, F>(&mut self, buf: &mut [u8], read: F) -> core::result::Result<usize, E>
+    where
+        F: FnOnce(&mut R, &mut [u8]) -> core::result::Result<usize, E>,
+    {
+        let size = match self.state.read_header(buf) {
+            Some(size) => size,
+            None => read(&mut self.reader, buf)?,
+        };
+        self.state.decrypt(&mut buf[..size]);
+        Ok(size)
     }
 }
 
+#[cfg(feature = "std")]
 impl<R> Read for Audio<R>
 where
     R: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let size = self.reader.read(buf)?;
-        Self::decrypt(&mut self.rc4_iter, &mut buf[..size]);
-        Ok(size)
+        self.read_with(buf, |reader, buf| reader.read(buf))
     }
 }
 
-impl<R> Debug for Audio<R>
+#[cfg(feature = "std")]
+impl<R> crate::format::AudioStream for Audio<R>
 where
     R: Read,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("Audio").field(&format!("{}", self.r#type)).finish()
+    fn decrypt_into(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        #[cfg(feature = "rayon")]
+        return self.read_to_end_parallel(buf);
+        #[cfg(not(feature = "rayon"))]
+        return self.read_to_end(buf);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Seek for Audio<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let state = &mut self.state;
+        let header_len = state.header.len() as u64;
+        let start = self.reader.stream_position()? - state.position.max(header_len);
+
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                let end = self.reader.seek(SeekFrom::End(0))?;
+                (end - start).checked_add_signed(n)
+            }
+            SeekFrom::Current(n) => state.position.checked_add_signed(n),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative position")
+        })?;
+
+        self.reader.seek(SeekFrom::Start(start + position.max(header_len)))?;
+        state.position = position;
+
+        Ok(position)
+    }
+}
+
+impl<R> Debug for Audio<R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("Audio").field(&self.r#type().to_string()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Type;
+    use alloc::vec::Vec;
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_apply_parallel() {
+        use super::{Cipher, PARALLEL_CHUNK_SIZE};
+
+        let ciphers = [Cipher::ncm(b"0123456789abcdef"), Cipher::Qmc];
+
+        // several chunks, starting in the middle of both the rc4 key stream and the qmc mask,
+        // whose 0x7FFF period does not divide the chunk size
+        for cipher in ciphers {
+            for position in [0, 1_000_003] {
+                let mut buf: Vec<u8> =
+                    (0..3 * PARALLEL_CHUNK_SIZE + 1000).map(|i| i as u8).collect();
+                let mut expected = buf.clone();
+                cipher.apply(position, &mut expected);
+                cipher.apply_parallel(position, &mut buf);
+                assert!(buf == expected, "{cipher:?} at {position}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_ext() {
+        assert_eq!(Type::from_ext("flac"), Type::Flac);
+        assert_eq!(Type::from_ext("MP3"), Type::Mp3);
+        assert_eq!(Type::from_ext("m4a"), Type::M4a);
+        assert_eq!(Type::from_ext("audio"), Type::Unknown);
+        assert_eq!(Type::from_ext(""), Type::Unknown);
+    }
+
+    #[test]
+    fn test_sniff() {
+        fn ogg(codec: &[u8]) -> Vec<u8> {
+            let mut page = b"OggS".to_vec();
+            page.resize(26, 0);
+            page.extend_from_slice(&[1, codec.len() as u8]);
+            page.extend_from_slice(codec);
+            page
+        }
+
+        let cases: &[(&[u8], Type)] = &[
+            (b"fLaC\0\0\0\x22", Type::Flac),
+            (b"ID3\x04\0\0", Type::Mp3),
+            (&[0xFF, 0xFB, 0x90, 0x64], Type::Mp3),
+            (&[0xFF, 0xF3, 0x90, 0x64], Type::Mp3),
+            (&[0xFF, 0xFA, 0x90, 0x64], Type::Mp3),
+            (&[0xFF, 0xF2, 0x90, 0x64], Type::Mp3),
+            (&[0xFF, 0xF1, 0x50, 0x80], Type::Aac),
+            (&[0xFF, 0xF9, 0x50, 0x80], Type::Aac),
+            (b"\0\0\0\x20ftypM4A \0\0\0\0", Type::M4a),
+            (b"\0\0\0\x20ftypM4B \0\0\0\0", Type::M4b),
+            (b"\0\0\0\x20ftypisom\0\0\x02\0", Type::Mp4),
+            (b"\0\0\0\x18ftypmp42\0\0\0\0", Type::Mp4),
+            (b"RIFF\x24\x08\0\0WAVEfmt ", Type::Wav),
+            (b"RIFF\x24\x08\0\0WEBPVP8 ", Type::Unknown),
+            (b"MAC \x96\x0f\0\0", Type::Ape),
+            (b"DSD \x1c\0\0\0\0\0\0\0", Type::Dsf),
+            (b"FRM8\0\0\0\0\0\0\x10\0DSD ", Type::Dff),
+            (&ogg(b"OpusHead"), Type::Opus),
+            (&ogg(b"\x01vorbis"), Type::Ogg),
+            (b"OggS", Type::Ogg),
+            (&[0xFF], Type::Unknown),
+            (b"", Type::Unknown),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(Type::sniff(data), *expected, "{data:02x?}");
+        }
     }
 }
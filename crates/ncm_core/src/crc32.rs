@@ -0,0 +1,46 @@
+/// CRC-32/ISO-HDLC, the checksum stored after the comment frame.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| {
+                (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+            })
+        });
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Crc32};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut crc = Crc32::default();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -1,90 +1,255 @@
 use crate::{
-    audio::{Audio, Type as AudioType},
+    audio::{Audio, Type as AudioType, MIN_AUDIO_LEN},
+    error::{Error, Frame, Result},
+    header::Header,
     image::Image,
-    key::{decrypt_key, decrypt_meta},
+    key_stream::KeyStream,
+    layout::{Layout, Span},
+    meta::MetaKind,
+    options::DecodeOptions,
+    parser::{Event, NcmParser, Truncation},
+    recovery::{self, Recovery},
+    source::{ByteSource, SourceError},
+    view::NcmView,
 };
-use anyhow::{ensure, Ok, Result};
-use base64::{engine::general_purpose::STANDARD as base64, Engine};
-use std::{io::Read, vec};
+use alloc::{string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom};
+
+const CHUNK_SIZE: usize = 0x10000;
 
 #[derive(Debug)]
-pub struct Decoder<R>
-where
-    R: Read,
-{
+pub struct Decoder<R> {
+    pub header: Header,
     pub key: Vec<u8>,
     pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
     pub meta: Vec<u8>,
     pub image: Option<Image>,
     pub audio: Audio<R>,
+    /// Set if the input ended before the audio, only with [`DecodeOptions::lenient`].
+    pub truncated: Option<Truncation>,
+    pub layout: Layout,
 }
 
+#[cfg(feature = "std")]
 impl<R> Decoder<R>
 where
     R: Read,
 {
-    pub fn decode(mut input: R) -> Result<Self> {
-        let mut buffer = [0; 10];
-        input.read_exact(&mut buffer)?;
-
-        ensure!(&buffer[..8] == b"CTENFDAM", "CTENFDAM file header mismatch");
+    pub fn decode(input: R) -> Result<Self> {
+        Self::decode_with(input, DecodeOptions::default())
+    }
 
-        let key = {
-            let (mut key, _) = Self::read_frame(&mut input)?;
+    pub fn decode_with(mut input: R, options: DecodeOptions) -> Result<Self> {
+        let min_len = if options.lenient { 0 } else { MIN_AUDIO_LEN };
+        let parts = Parts::decode(&mut ReadInput(&mut input), options)?;
 
-            let key = decrypt_key(&mut key)?;
+        let position = parts.audio_offset;
+        let audio = Audio::try_new_with(input, &parts.key, min_len)
+            .map_err(|err| Error::io(position, err))?
+            .with_offset(position);
 
-            ensure!(&key[..17] == b"neteasecloudmusic", "Invalid ncm key");
+        Ok(parts.with_audio(audio))
+    }
+}
 
-            key[17..].to_vec()
-        };
+#[cfg(feature = "std")]
+impl<R> Decoder<R>
+where
+    R: Read + Seek,
+{
+    /// Reads only the key, comment and meta, seeking over the cover image and leaving the audio unread.
+    pub fn probe(input: R) -> Result<Probe> {
+        Self::probe_with(input, DecodeOptions::default())
+    }
 
-        let comment = {
-            let (mut comment, _) = Self::read_frame(&mut input)?;
-            if !comment.is_empty() {
-                comment.iter_mut().for_each(|byte| *byte ^= 99);
+    /// [`Decoder::probe`] with `options`, `load_image` is ignored as the image is skipped.
+    pub fn probe_with(mut input: R, options: DecodeOptions) -> Result<Probe> {
+        let min_len = if options.lenient { 0 } else { MIN_AUDIO_LEN as u64 };
+        let options = DecodeOptions { load_image: false, ..options };
+        let Parts {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image_offset,
+            image_len,
+            audio_offset,
+            truncated,
+            mut layout,
+            ..
+        } = Parts::decode(&mut SeekInput(&mut input), options)?;
 
-                ensure!(&comment[..22] == b"163 key(Don't modify):", "Invalid comment");
+        let end = input.seek(SeekFrom::End(0)).map_err(|err| Error::io(audio_offset, err))?;
+        input.seek(SeekFrom::Start(audio_offset)).map_err(|err| Error::io(audio_offset, err))?;
+        if truncated.is_none() {
+            if end < audio_offset + min_len {
+                return Err(Error::UnexpectedEof { offset: audio_offset });
             }
+            layout.audio.len = end - audio_offset;
+        }
 
-            comment
-        };
+        Ok(Probe {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image_offset,
+            image_len,
+            audio_offset,
+            truncated,
+            layout,
+        })
+    }
+}
 
-        let meta = if !comment.is_empty() {
-            let meta = &comment[22..];
-            let mut meta = base64.decode(meta)?;
+impl<S> Decoder<S>
+where
+    S: ByteSource,
+{
+    /// Decodes from a [`ByteSource`], which does not need `std`.
+    pub fn decode_source(input: S) -> Result<Self> {
+        Self::decode_source_with(input, DecodeOptions::default())
+    }
 
-            let meta = decrypt_meta(&mut meta)?;
+    pub fn decode_source_with(mut input: S, options: DecodeOptions) -> Result<Self> {
+        let min_len = if options.lenient { 0 } else { MIN_AUDIO_LEN };
+        let parts = Parts::decode(&mut SourceInput(&mut input), options)?;
 
-            ensure!(&meta[..6] == b"music:", "Invalid meta");
-            meta[6..].to_vec()
-        } else {
-            vec![]
-        };
+        let audio = Audio::from_source_at(input, &parts.key, parts.audio_offset, min_len)?;
 
-        Self::skip(&mut input, 5)?;
+        Ok(parts.with_audio(audio))
+    }
+}
 
-        let image = {
-            let offset = Self::read_len(&mut input)?;
+impl<'a> Decoder<&'a [u8]> {
+    /// Decodes an ncm file held in memory without copying its image or audio.
+    pub fn from_slice(data: &'a [u8]) -> Result<NcmView<'a>> {
+        Self::from_slice_with(data, DecodeOptions::default())
+    }
 
-            let (image, img_len) = Self::read_frame(&mut input)?;
+    /// [`Decoder::from_slice`] with `options`, `load_image` is ignored as the image is borrowed.
+    pub fn from_slice_with(data: &'a [u8], options: DecodeOptions) -> Result<NcmView<'a>> {
+        let options = DecodeOptions { load_image: false, ..options };
+        let result = Self::view(data, options.clone());
+        if !options.recover {
+            return result;
+        }
 
-            if offset > img_len {
-                Self::skip(&mut input, (offset - img_len) as usize)?;
+        match &result {
+            Ok(view) if view.audio_type() != AudioType::Unknown => result,
+            Ok(_)
+            | Err(Error::FrameTooLarge { frame: Frame::Image, .. } | Error::UnexpectedEof { .. }) => {
+                Self::recover(data, options).map_or(result, Ok)
             }
+            Err(_) => result,
+        }
+    }
 
-            if img_len > 0 {
-                Some(image.into())
-            } else {
-                None
-            }
-        };
+    fn view(data: &'a [u8], options: DecodeOptions) -> Result<NcmView<'a>> {
+        let lenient = options.lenient;
+        let Parts {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image_offset,
+            image_len,
+            audio_offset,
+            truncated,
+            mut layout,
+            ..
+        } = Parts::decode(&mut SliceInput { data, position: 0 }, options)?;
 
-        let audio = Audio::try_new(input, &key)?;
+        // the parser has gone over both, so they are in bounds, the image may be cut short
+        let image_end = (image_offset + image_len).min(audio_offset);
+        let image = (image_len > 0 && image_end > image_offset)
+            .then(|| &data[image_offset as usize..image_end as usize]);
+        let audio = &data[audio_offset as usize..];
+        if audio.len() < MIN_AUDIO_LEN && !lenient {
+            return Err(Error::UnexpectedEof { offset: audio_offset });
+        }
+        if truncated.is_none() {
+            layout.audio.len = audio.len() as u64;
+        }
 
-        Ok(Self { key, comment, meta, image, audio })
+        let key_stream = KeyStream::new(&key);
+        Ok(NcmView {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image,
+            audio,
+            audio_offset,
+            key_stream,
+            truncated,
+            recovered: None,
+            layout,
+        })
     }
 
+    /// Reads the frames up to the image again, trusting none of the cover frame lengths,
+    /// and searches for the audio from the start of the image.
+    fn recover(data: &'a [u8], options: DecodeOptions) -> Option<NcmView<'a>> {
+        let relaxed = DecodeOptions { lenient: true, max_image_len: u64::MAX, ..options.clone() };
+        let Parts {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image_offset,
+            audio_offset,
+            truncated,
+            mut layout,
+            ..
+        } = Parts::decode(&mut SliceInput { data, position: 0 }, relaxed).ok()?;
+        if image_offset == 0 {
+            return None;
+        }
+
+        let declared_offset = truncated.map_or(audio_offset, |truncation| {
+            truncation.offset.saturating_add(truncation.missing)
+        });
+
+        let key_stream = KeyStream::new(&key);
+        let start = image_offset as usize;
+        let end = start.saturating_add(options.max_image_len.try_into().unwrap_or(usize::MAX));
+        let (audio_offset, _) = recovery::find_audio(data, &key_stream, start..end)?;
+        layout.image.len = (audio_offset - start) as u64;
+        layout.audio =
+            Span { offset: audio_offset as u64, len: (data.len() - audio_offset) as u64 };
+
+        Some(NcmView {
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image: (audio_offset > start).then(|| &data[start..audio_offset]),
+            audio: &data[audio_offset..],
+            audio_offset: audio_offset as u64,
+            key_stream,
+            truncated: None,
+            recovered: Some(Recovery { declared_offset, audio_offset: audio_offset as u64 }),
+            layout,
+        })
+    }
+
+    /// [`Decoder::from_slice`] for a memory mapped file.
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap(mmap: &'a memmap2::Mmap) -> Result<NcmView<'a>> {
+        Self::from_slice(mmap)
+    }
+}
+
+impl<R> Decoder<R> {
     pub fn audio_type(&self) -> AudioType {
         self.audio.r#type()
     }
@@ -93,27 +258,273 @@ where
         self.audio.ext()
     }
 
-    fn read_frame(input: &mut R) -> Result<(Vec<u8>, u32)> {
-        let len = Self::read_len(input)?;
-        if len > 0 {
-            let mut data = vec![0; len.try_into()?];
-            input.read_exact(&mut data)?;
-            Ok((data, len))
-        } else {
-            Ok((vec![], 0))
+    /// The offset of the audio data from the start of the ncm file.
+    pub fn audio_offset(&self) -> u64 {
+        self.audio.offset()
+    }
+}
+
+/// The result of [`Decoder::probe`], everything but the image and audio data.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub header: Header,
+    pub key: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
+    pub meta: Vec<u8>,
+    /// The offset of the image data from the start of the ncm file.
+    pub image_offset: u64,
+    /// The length of the image data, `0` if there is no image.
+    pub image_len: u64,
+    /// The offset of the audio data from the start of the ncm file.
+    pub audio_offset: u64,
+    /// Set if the input ended before the audio, only with [`DecodeOptions::lenient`].
+    pub truncated: Option<Truncation>,
+    pub layout: Layout,
+}
+
+/// Everything [`Decoder`] reads ahead of the audio.
+pub(crate) struct Parts {
+    pub header: Header,
+    pub key: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
+    pub meta: Vec<u8>,
+    pub image: Option<Image>,
+    pub image_offset: u64,
+    pub image_len: u64,
+    pub audio_offset: u64,
+    pub truncated: Option<Truncation>,
+    pub layout: Layout,
+}
+
+impl Parts {
+    /// Drives a [`Collector`] from `input` until the start of the audio.
+    fn decode<I>(input: &mut I, options: DecodeOptions) -> Result<Self>
+    where
+        I: Input,
+    {
+        let mut collector = Collector::new(options);
+
+        let mut buffer = vec![];
+        while !collector.is_done() {
+            if let Some(len) = collector.skippable() {
+                if input.skip(len, collector.offset())? {
+                    collector.skip(len)?;
+                    continue;
+                }
+            }
+
+            buffer.resize(collector.wanted(), 0);
+            let size = input.read(&mut buffer, collector.offset())?;
+            collector.feed(&buffer[..size])?;
         }
+
+        Ok(collector.finish())
     }
 
-    fn read_len(input: &mut R) -> Result<u32> {
-        let mut buffer = [0; 4];
-        input.read_exact(&mut buffer)?;
-        Ok(u32::from_le_bytes(buffer))
+    fn with_audio<R>(self, audio: Audio<R>) -> Decoder<R> {
+        let Self { header, key, comment, meta_kind, meta, image, truncated, layout, .. } = self;
+        Decoder { header, key, comment, meta_kind, meta, image, audio, truncated, layout }
     }
+}
 
-    fn skip(input: &mut R, i: usize) -> Result<()> {
-        let mut buffer = vec![0; i];
-        input.read_exact(&mut buffer)?;
+/// Collects the [`Parts`] from the events of an [`NcmParser`], whichever way the input is read.
+pub(crate) struct Collector {
+    parser: NcmParser,
+    lenient: bool,
+    header: Header,
+    key: Vec<u8>,
+    comment: Vec<u8>,
+    meta_kind: MetaKind,
+    meta: Vec<u8>,
+    image: Option<Image>,
+    truncated: Option<Truncation>,
+}
+
+impl Collector {
+    pub(crate) fn new(options: DecodeOptions) -> Self {
+        Self {
+            lenient: options.lenient,
+            parser: NcmParser::with_options(options),
+            header: Header::default(),
+            key: vec![],
+            comment: vec![],
+            meta_kind: MetaKind::default(),
+            meta: vec![],
+            image: None,
+            truncated: None,
+        }
+    }
 
+    /// Whether the audio has been reached, or the input has ended early in lenient mode.
+    pub(crate) fn is_done(&self) -> bool {
+        self.parser.is_audio() || self.truncated.is_some()
+    }
+
+    /// How many bytes to read next.
+    pub(crate) fn wanted(&self) -> usize {
+        self.parser.wanted().min(CHUNK_SIZE as u64) as usize
+    }
+
+    /// The offset of the next byte in the input.
+    pub(crate) fn offset(&self) -> u64 {
+        self.parser.offset()
+    }
+
+    /// Feeds bytes read from the input, which has ended if `data` is empty.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Result<()> {
+        let events = match data {
+            [] if self.lenient => {
+                let (events, truncation) = self.parser.finish_truncated()?;
+                self.truncated = Some(truncation);
+                events
+            }
+            [] => return Err(Error::UnexpectedEof { offset: self.parser.offset() }),
+            data => self.parser.feed(data)?,
+        };
+        self.collect(events);
         Ok(())
     }
+
+    /// How many bytes may be skipped instead of read, if any.
+    fn skippable(&self) -> Option<u64> {
+        self.parser.skippable().then(|| self.parser.wanted())
+    }
+
+    fn skip(&mut self, len: u64) -> Result<()> {
+        let events = self.parser.skip(len)?;
+        self.collect(events);
+        Ok(())
+    }
+
+    fn collect(&mut self, events: Vec<Event>) {
+        for event in events {
+            match event {
+                Event::Key(data) => self.key = data,
+                Event::Comment(data) => self.comment = data,
+                Event::Meta(kind, data) => (self.meta_kind, self.meta) = (kind, data),
+                Event::Header(data) => self.header = data,
+                Event::Image(data) => self.image = Some(data),
+                Event::AudioChunk(_) => unreachable!(),
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Parts {
+        Parts {
+            header: self.header,
+            key: self.key,
+            comment: self.comment,
+            meta_kind: self.meta_kind,
+            meta: self.meta,
+            image: self.image,
+            image_offset: self.parser.image_offset(),
+            image_len: self.parser.image_len(),
+            audio_offset: self.parser.position(),
+            truncated: self.truncated,
+            layout: self.parser.layout(),
+        }
+    }
+}
+
+/// How [`Parts::decode`] pulls bytes from a reader.
+trait Input {
+    /// Reads up to `buf.len()` bytes, `0` once the input is exhausted.
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize>;
+
+    /// Skips `len` bytes, returning `false` if they have to be read instead.
+    fn skip(&mut self, _len: u64, _offset: u64) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[cfg(feature = "std")]
+struct ReadInput<'a, R>(&'a mut R);
+
+#[cfg(feature = "std")]
+impl<R> Input for ReadInput<'_, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        read(self.0, buf, offset)
+    }
+}
+
+#[cfg(feature = "std")]
+struct SeekInput<'a, R>(&'a mut R);
+
+#[cfg(feature = "std")]
+impl<R> Input for SeekInput<'_, R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        read(self.0, buf, offset)
+    }
+
+    /// Skips only if all of `len` is there, otherwise the rest is read to find where it ends.
+    fn skip(&mut self, len: u64, offset: u64) -> Result<bool> {
+        let seek = |reader: &mut R, pos| reader.seek(pos).map_err(|err| Error::io(offset, err));
+        let position = seek(self.0, SeekFrom::Current(0))?;
+        let end = seek(self.0, SeekFrom::End(0))?;
+
+        let skipped = end - position.min(end) >= len;
+        seek(self.0, SeekFrom::Start(if skipped { position + len } else { position }))?;
+        Ok(skipped)
+    }
+}
+
+struct SliceInput<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Input for SliceInput<'_> {
+    fn read(&mut self, buf: &mut [u8], _offset: u64) -> Result<usize> {
+        let data = &self.data[self.position..];
+        let size = data.len().min(buf.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        self.position += size;
+        Ok(size)
+    }
+
+    /// Skips only if all of `len` is there, otherwise the rest is read to find where it ends.
+    fn skip(&mut self, len: u64, _offset: u64) -> Result<bool> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.position.checked_add(len))
+            .filter(|&end| end <= self.data.len());
+
+        if let Some(end) = end {
+            self.position = end;
+        }
+        Ok(end.is_some())
+    }
+}
+
+struct SourceInput<'a, S>(&'a mut S);
+
+impl<S> Input for SourceInput<'_, S>
+where
+    S: ByteSource,
+{
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.0.read(buf).map_err(|SourceError| Error::Source { offset })
+    }
+}
+
+/// [`Read::read`], retrying on [`std::io::ErrorKind::Interrupted`].
+#[cfg(feature = "std")]
+fn read<R>(reader: &mut R, buf: &mut [u8], offset: u64) -> Result<usize>
+where
+    R: Read,
+{
+    loop {
+        match reader.read(buf) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            result => return result.map_err(|err| Error::io(offset, err)),
+        }
+    }
 }
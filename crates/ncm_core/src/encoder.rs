@@ -0,0 +1,90 @@
+use crate::{
+    crc32::crc32,
+    header::MAGIC,
+    key::KeySet,
+    meta::{Meta, MetaKind},
+    meta_key::encode_163_key_with,
+    ncm_rc4::NcmRc4,
+};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+const VERSION: [u8; 2] = [0x01, 0x70];
+const GAP: u8 = 0x01;
+
+/// Packs plain audio into an ncm container, the reverse of [`crate::decoder::Decoder`].
+///
+/// `key` and `meta` have the same shape as the fields of a decoded file,
+/// i.e. without the `neteasecloudmusic` and `music:` or `dj:` prefixes.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoder<'a> {
+    pub key: &'a [u8],
+    pub meta_kind: MetaKind,
+    pub meta: &'a [u8],
+    pub image: Option<&'a [u8]>,
+    /// The keys of the key and comment frames, usually [`KeySet::default`].
+    pub keys: KeySet,
+}
+
+impl Encoder<'_> {
+    pub fn encode<R, W>(&self, mut audio: R, mut output: W) -> Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION);
+
+        let key = self.keys.encrypt_key(&[b"neteasecloudmusic", self.key].concat());
+        Self::write_frame(&mut header, &key)?;
+
+        let mut comment = self.comment();
+        self.keys.apply_comment(&mut comment);
+        Self::write_frame(&mut header, &comment)?;
+
+        output.write_all(&header)?;
+        output.write_all(&crc32(&header).to_le_bytes())?;
+        output.write_all(&[GAP])?;
+
+        let image = self.image.unwrap_or_default();
+        output.write_all(&Self::frame_len(image)?.to_le_bytes())?;
+        Self::write_frame(&mut output, image)?;
+
+        let rc4 = NcmRc4::new(self.key);
+        let mut position = 0;
+        let mut buffer = [0; 0x8000];
+        loop {
+            let size = audio.read(&mut buffer)?;
+            if size == 0 {
+                break;
+            }
+
+            let chunk = &mut buffer[..size];
+            rc4.apply(position, chunk);
+            output.write_all(chunk)?;
+            position += size as u64;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    /// The plain text `163 key(Don't modify):` comment for `meta`, empty if there is no meta.
+    pub fn comment(&self) -> Vec<u8> {
+        if self.meta.is_empty() {
+            return vec![];
+        }
+
+        encode_163_key_with(&Meta { kind: self.meta_kind, json: self.meta.to_vec() }, &self.keys)
+            .into_bytes()
+    }
+
+    fn write_frame<W: Write>(output: &mut W, data: &[u8]) -> Result<()> {
+        output.write_all(&Self::frame_len(data)?.to_le_bytes())?;
+        output.write_all(data)?;
+        Ok(())
+    }
+
+    fn frame_len(data: &[u8]) -> Result<u32> {
+        data.len().try_into().map_err(|_| Error::new(ErrorKind::InvalidInput, "frame too large"))
+    }
+}
@@ -0,0 +1,123 @@
+use core::fmt::{self, Display};
+#[cfg(feature = "std")]
+use std::io;
+
+/// Errors returned while decoding an ncm file.
+///
+/// Every variant carries the byte offset, from the start of the file,
+/// of the field or frame in which the failure happened.
+/// More variants may be added in minor releases.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input does not start with the `CTENFDAM` magic, it is not an ncm file.
+    HeaderMismatch { offset: u64 },
+    /// No format in a [`crate::format::Registry`] recognises the input.
+    UnsupportedFormat { offset: u64 },
+    /// The decrypted key frame does not start with `neteasecloudmusic`.
+    InvalidKey { offset: u64 },
+    /// The comment frame does not start with `163 key(Don't modify):`.
+    InvalidComment { offset: u64 },
+    /// The comment frame is not valid base64.
+    Base64 { offset: u64, source: base64::DecodeError },
+    /// A frame decrypted with invalid AES padding.
+    Padding { offset: u64 },
+    /// The decrypted meta does not start with `music:`.
+    InvalidMeta { offset: u64 },
+    /// The stored CRC32 does not match the key and comment frames, only checked if requested.
+    CrcMismatch { offset: u64, expected: u32, actual: u32 },
+    /// A frame is longer than the limit set in [`crate::options::DecodeOptions`].
+    FrameTooLarge { offset: u64, frame: Frame, len: u64, limit: u64 },
+    /// The input ended before a field or frame was complete.
+    UnexpectedEof { offset: u64 },
+    /// A [`crate::source::ByteSource`] failed to read.
+    Source { offset: u64 },
+    /// Any other I/O error of the underlying reader.
+    #[cfg(feature = "std")]
+    Io { offset: u64, source: io::Error },
+}
+
+impl Error {
+    #[cfg(feature = "std")]
+    pub(crate) fn io(offset: u64, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::UnexpectedEof => Error::UnexpectedEof { offset },
+            _ => Error::Io { offset, source },
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        match self {
+            Error::HeaderMismatch { offset }
+            | Error::UnsupportedFormat { offset }
+            | Error::InvalidKey { offset }
+            | Error::InvalidComment { offset }
+            | Error::Base64 { offset, .. }
+            | Error::Padding { offset }
+            | Error::InvalidMeta { offset }
+            | Error::CrcMismatch { offset, .. }
+            | Error::FrameTooLarge { offset, .. }
+            | Error::UnexpectedEof { offset }
+            | Error::Source { offset } => *offset,
+            #[cfg(feature = "std")]
+            Error::Io { offset, .. } => *offset,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HeaderMismatch { .. } => write!(f, "CTENFDAM file header mismatch"),
+            Error::UnsupportedFormat { .. } => write!(f, "Unsupported encrypted format"),
+            Error::InvalidKey { .. } => write!(f, "Invalid ncm key"),
+            Error::InvalidComment { .. } => write!(f, "Invalid comment"),
+            Error::Base64 { source, .. } => write!(f, "Invalid base64 in comment: {source}"),
+            Error::Padding { .. } => write!(f, "Invalid padding"),
+            Error::InvalidMeta { .. } => write!(f, "Invalid meta"),
+            Error::CrcMismatch { expected, actual, .. } => {
+                write!(f, "CRC32 mismatch, expected {expected:08x} but found {actual:08x}")
+            }
+            Error::FrameTooLarge { frame, len, limit, .. } => {
+                write!(f, "{frame} frame of {len} bytes exceeds the limit of {limit} bytes")
+            }
+            Error::UnexpectedEof { .. } => write!(f, "Unexpected end of file"),
+            Error::Source { .. } => write!(f, "Failed to read from source"),
+            #[cfg(feature = "std")]
+            Error::Io { source, .. } => write!(f, "{source}"),
+        }?;
+
+        write!(f, " at offset {}", self.offset())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Base64 { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The frames whose length is limited by [`crate::options::DecodeOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    Key,
+    Comment,
+    Image,
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Key => write!(f, "Key"),
+            Frame::Comment => write!(f, "Comment"),
+            Frame::Image => write!(f, "Image"),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,352 @@
+use crate::{
+    audio::{Type as AudioType, SNIFF_LEN},
+    decoder::Decoder,
+    error::{Error, Result},
+    header::MAGIC,
+    image::Image,
+    layout::Layout,
+    meta::Meta,
+    options::DecodeOptions,
+    parser::Truncation,
+    qmc::Qmc,
+    recovery::Recovery,
+    view::NcmView,
+};
+use std::{
+    boxed::Box,
+    io::{self, Cursor, Read},
+    vec,
+    vec::Vec,
+};
+
+/// How sure an [`EncryptedFormat`] is that it can open an input, from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    No,
+    /// The bytes fit, but the format has no magic of its own to tell it apart.
+    Maybe,
+    /// The input starts with the magic of the format.
+    Certain,
+}
+
+/// The decrypted audio of a [`DecodedStream`].
+pub trait AudioStream: Read {
+    /// Decrypts the rest of the audio onto the end of `buf`, like [`Read::read_to_end`].
+    ///
+    /// Audio held in memory is copied into `buf` once and decrypted in place, on the rayon
+    /// thread pool with the `rayon` feature.
+    fn decrypt_into(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.read_to_end(buf)
+    }
+}
+
+impl AudioStream for &[u8] {}
+
+impl<T> AudioStream for Cursor<T> where T: AsRef<[u8]> {}
+
+/// The decrypted parts of an input, as opened by an [`EncryptedFormat`].
+pub struct DecodedStream<'a> {
+    pub audio: Box<dyn AudioStream + 'a>,
+    /// The type sniffed from the first bytes of the decrypted audio.
+    pub audio_type: AudioType,
+    /// The key of the audio, empty if the format has none.
+    pub key: Vec<u8>,
+    /// The plain text comment, e.g. the `163 key(Don't modify):` string of an ncm file, empty if there is none.
+    pub comment: Vec<u8>,
+    pub meta: Option<Meta>,
+    pub cover: Option<Image>,
+    /// Where the parts of the input sit, if the format has any besides the audio.
+    pub layout: Option<Layout>,
+    /// Set if the input ended early, only with [`DecodeOptions::lenient`].
+    pub truncated: Option<Truncation>,
+    /// Set if the audio was found by searching, only with [`DecodeOptions::recover`].
+    pub recovered: Option<Recovery>,
+}
+
+impl DecodedStream<'_> {
+    /// Decrypts the rest of the audio onto the end of `buf`, see [`AudioStream::decrypt_into`].
+    pub fn decrypt_into(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.audio.decrypt_into(buf)
+    }
+}
+
+/// An encrypted audio container, such as ncm.
+pub trait EncryptedFormat {
+    /// A short name for messages, e.g. `ncm`.
+    fn name(&self) -> &'static str;
+
+    /// Guesses from the first [`SNIFF_LEN`] bytes of an input whether it is in this format.
+    fn sniff(&self, head: &[u8]) -> Confidence;
+
+    /// Opens an input read from its start, e.g. a file.
+    fn open<'a>(
+        &self,
+        input: Box<dyn Read + 'a>,
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>>;
+
+    /// Opens the whole input held in memory, e.g. a memory mapped file, by default with
+    /// [`EncryptedFormat::open`].
+    fn open_slice<'a>(
+        &self,
+        input: &'a [u8],
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>> {
+        self.open(Box::new(input), options)
+    }
+}
+
+/// The ncm format, opened with [`Decoder::decode_with`], or [`Decoder::from_slice_with`] in memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ncm;
+
+impl Ncm {
+    fn stream<'a>(view: NcmView<'_>, audio: Box<dyn AudioStream + 'a>) -> DecodedStream<'a> {
+        DecodedStream {
+            audio,
+            audio_type: view.audio_type(),
+            meta: (!view.meta.is_empty())
+                .then(|| Meta { kind: view.meta_kind, json: view.meta.clone() }),
+            key: view.key,
+            comment: view.comment,
+            cover: view.image.map(|image| Image::from(image.to_vec())),
+            layout: Some(view.layout),
+            truncated: view.truncated,
+            recovered: view.recovered,
+        }
+    }
+}
+
+impl EncryptedFormat for Ncm {
+    fn name(&self) -> &'static str {
+        "ncm"
+    }
+
+    fn sniff(&self, head: &[u8]) -> Confidence {
+        if head.starts_with(MAGIC) {
+            Confidence::Certain
+        } else {
+            Confidence::No
+        }
+    }
+
+    fn open<'a>(
+        &self,
+        mut input: Box<dyn Read + 'a>,
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>> {
+        if options.recover {
+            // searching for the audio needs the whole input
+            let mut data = vec![];
+            input.read_to_end(&mut data).map_err(|err| Error::io(0, err))?;
+            let view = Decoder::from_slice_with(&data, options.clone())?;
+            let mut audio = vec![0; view.audio.len()];
+            view.decrypt_audio_into(&mut audio);
+
+            return Ok(Self::stream(view, Box::new(Cursor::new(audio))));
+        }
+
+        let Decoder { key, comment, meta_kind, meta, image, audio, truncated, layout, .. } =
+            Decoder::decode_with(input, options.clone())?;
+
+        Ok(DecodedStream {
+            audio_type: audio.r#type(),
+            audio: Box::new(audio),
+            key,
+            comment,
+            meta: (!meta.is_empty()).then_some(Meta { kind: meta_kind, json: meta }),
+            cover: image,
+            layout: Some(layout),
+            truncated,
+            recovered: None,
+        })
+    }
+
+    fn open_slice<'a>(
+        &self,
+        input: &'a [u8],
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>> {
+        let view = Decoder::from_slice_with(input, options.clone())?;
+        let audio = view.audio_reader();
+
+        Ok(Self::stream(view, Box::new(audio)))
+    }
+}
+
+/// The formats an input is matched against, by default [`Ncm`] and [`Qmc`].
+pub struct Registry {
+    formats: Vec<Box<dyn EncryptedFormat>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Ncm);
+        registry.register(Qmc);
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry without any format.
+    pub fn new() -> Self {
+        Self { formats: Vec::new() }
+    }
+
+    /// Adds `format`, which loses ties against the formats registered before it.
+    pub fn register<F>(&mut self, format: F)
+    where
+        F: EncryptedFormat + 'static,
+    {
+        self.formats.push(Box::new(format));
+    }
+
+    /// The first format certain about `head`, otherwise the most confident one, if any is.
+    pub fn detect(&self, head: &[u8]) -> Option<&dyn EncryptedFormat> {
+        let mut best = None;
+        for format in &self.formats {
+            match format.sniff(head) {
+                Confidence::Certain => return Some(&**format),
+                confidence if confidence > best.map_or(Confidence::No, |(best, _)| best) => {
+                    best = Some((confidence, &**format));
+                }
+                _ => {}
+            }
+        }
+
+        best.map(|(_, format)| format)
+    }
+
+    /// Opens `input` with the format [`Registry::detect`] picks from its first [`SNIFF_LEN`] bytes.
+    pub fn open<'a, R>(&self, mut input: R, options: &DecodeOptions) -> Result<DecodedStream<'a>>
+    where
+        R: Read + 'a,
+    {
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        (&mut input)
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .map_err(|err| Error::io(0, err))?;
+        let format = self.detect(&head).ok_or(Error::UnsupportedFormat { offset: 0 })?;
+
+        format.open(Box::new(Cursor::new(head).chain(input)), options)
+    }
+
+    /// [`Registry::open`] for an input held in memory, see [`EncryptedFormat::open_slice`].
+    pub fn open_slice<'a>(
+        &self,
+        input: &'a [u8],
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>> {
+        let head = &input[..input.len().min(SNIFF_LEN)];
+        let format = self.detect(head).ok_or(Error::UnsupportedFormat { offset: 0 })?;
+
+        format.open_slice(input, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Confidence, DecodedStream, EncryptedFormat, Ncm, Registry};
+    use crate::{audio::Type as AudioType, options::DecodeOptions, Error, Result};
+    use std::{
+        boxed::Box,
+        io::{Cursor, Read},
+    };
+
+    struct Plain(Confidence);
+
+    /// A format which must never be asked.
+    struct Unreachable;
+
+    impl EncryptedFormat for Unreachable {
+        fn name(&self) -> &'static str {
+            "unreachable"
+        }
+
+        fn sniff(&self, _head: &[u8]) -> Confidence {
+            unreachable!()
+        }
+
+        fn open<'a>(
+            &self,
+            _input: Box<dyn Read + 'a>,
+            _options: &DecodeOptions,
+        ) -> Result<DecodedStream<'a>> {
+            unreachable!()
+        }
+    }
+
+    impl EncryptedFormat for Plain {
+        fn name(&self) -> &'static str {
+            "plain"
+        }
+
+        fn sniff(&self, _head: &[u8]) -> Confidence {
+            self.0
+        }
+
+        fn open<'a>(
+            &self,
+            mut input: Box<dyn Read + 'a>,
+            _options: &DecodeOptions,
+        ) -> Result<DecodedStream<'a>> {
+            let mut audio = vec![];
+            input.read_to_end(&mut audio).map_err(|err| Error::io(0, err))?;
+
+            Ok(DecodedStream {
+                audio: Box::new(Cursor::new(audio)),
+                audio_type: AudioType::Unknown,
+                key: vec![],
+                comment: vec![],
+                meta: None,
+                cover: None,
+                layout: None,
+                truncated: None,
+                recovered: None,
+            })
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::default();
+        assert_eq!(registry.detect(b"CTENFDAM\x01\x70").unwrap().name(), "ncm");
+        assert!(registry.detect(b"fLaC").is_none());
+        assert!(matches!(
+            registry.open_slice(b"fLaC", &DecodeOptions::default()),
+            Err(Error::UnsupportedFormat { offset: 0 })
+        ));
+        assert!(matches!(
+            registry.open(&b"fLaC"[..], &DecodeOptions::default()),
+            Err(Error::UnsupportedFormat { offset: 0 })
+        ));
+
+        let mut registry = Registry::new();
+        registry.register(Plain(Confidence::Maybe));
+        registry.register(Ncm);
+        assert_eq!(registry.detect(b"CTENFDAM\x01\x70").unwrap().name(), "ncm");
+        assert_eq!(registry.detect(b"fLaC").unwrap().name(), "plain");
+
+        // an input longer than the sniffed head is read whole
+        let input = [b"fLaC".repeat(100), b"end".to_vec()].concat();
+        for mut stream in [
+            registry.open_slice(&input, &DecodeOptions::default()).unwrap(),
+            registry.open(&*input, &DecodeOptions::default()).unwrap(),
+        ] {
+            let mut audio = vec![];
+            stream.decrypt_into(&mut audio).unwrap();
+            assert_eq!(audio, input);
+        }
+    }
+
+    #[test]
+    fn test_detect_certain() {
+        let mut registry = Registry::new();
+        registry.register(Ncm);
+        registry.register(Unreachable);
+        // the first certain format wins, the others are not asked
+        assert_eq!(registry.detect(b"CTENFDAM\x01\x70").unwrap().name(), "ncm");
+    }
+}
@@ -0,0 +1,13 @@
+/// The first eight bytes of every ncm file.
+pub const MAGIC: &[u8; 8] = b"CTENFDAM";
+
+/// The fixed fields of an ncm file around the key and comment frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Header {
+    /// The two bytes after the `CTENFDAM` magic, e.g. `[0x01, 0x70]`.
+    pub version: [u8; 2],
+    /// The CRC32 of everything from the magic to the end of the comment frame.
+    pub crc: u32,
+    /// The byte after the CRC32, usually `0x01`.
+    pub gap: u8,
+}
@@ -1,6 +1,7 @@
-use std::fmt::Debug;
+use alloc::vec::Vec;
+use core::fmt::Debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     Png,
     Jpeg,
@@ -10,12 +11,26 @@ pub enum Type {
     Unknown,
 }
 
-#[derive(Clone)]
-pub struct Image(Type, Vec<u8>);
+impl Type {
+    /// Guesses the format from the first 12 bytes of the image data, shorter data is fine.
+    pub fn sniff(data: &[u8]) -> Self {
+        // none of the magics contain a zero byte, so padding short data never matches more
+        let mut header = [0; 12];
+        let len = data.len().min(header.len());
+        header[..len].copy_from_slice(&data[..len]);
+
+        match (&header[..4], &header[4..8], &header[8..12]) {
+            (b"\x89PNG", [0x0D, 0x0A, 0x1A, 0x0A], _) => Type::Png,
+            ([0xFF, 0xD8, 0xFF, 0xE0 | 0xE1 | 0xE2 | 0xE3 | 0xE8], ..) => Type::Jpeg,
+            (b"RIFF", _, b"WEBP") => Type::Webp,
+            (b"GIF8", ..) => Type::Gif,
+            ([b'B', b'M', ..], ..) => Type::Bmp,
+            _ => Type::Unknown,
+        }
+    }
 
-impl Image {
     pub fn ext(&self) -> &'static str {
-        match &self.0 {
+        match self {
             Type::Png => "png",
             Type::Jpeg => "jpeg",
             Type::Gif => "gif",
@@ -26,7 +41,7 @@ impl Image {
     }
 
     pub fn mime_type(&self) -> &'static str {
-        match &self.0 {
+        match self {
             Type::Png => "image/png",
             Type::Jpeg => "image/jpeg",
             Type::Gif => "image/gif",
@@ -35,6 +50,23 @@ impl Image {
             Type::Unknown => "image/*",
         }
     }
+}
+
+#[derive(Clone)]
+pub struct Image(Type, Vec<u8>);
+
+impl Image {
+    pub fn r#type(&self) -> Type {
+        self.0
+    }
+
+    pub fn ext(&self) -> &'static str {
+        self.0.ext()
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        self.0.mime_type()
+    }
 
     pub fn data(&self) -> &Vec<u8> {
         &self.1
@@ -47,19 +79,12 @@ impl Image {
 
 impl From<Vec<u8>> for Image {
     fn from(value: Vec<u8>) -> Self {
-        match (&value[..4], &value[4..8], &value[8..12]) {
-            (b"\x89PNG", [0x0D, 0x0A, 0x1A, 0x0A], _) => Image(Type::Png, value),
-            ([0xFF, 0xD8, 0xFF, 0xE0 | 0xE1 | 0xE2 | 0xE3 | 0xE8], ..) => Image(Type::Jpeg, value),
-            (b"RIFF", _, b"WEBP") => Image(Type::Webp, value),
-            (b"GIF8", ..) => Image(Type::Gif, value),
-            ([b'B', b'M', ..], ..) => Image(Type::Bmp, value),
-            _ => Image(Type::Unknown, value),
-        }
+        Image(Type::sniff(&value), value)
     }
 }
 
 impl Debug for Image {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Image").field("type", &self.0).field("size", &self.1.len()).finish()
     }
 }
@@ -67,6 +92,7 @@ impl Debug for Image {
 #[cfg(test)]
 mod tests {
     use super::Image;
+    use alloc::vec;
 
     #[test]
     fn test_image() {
@@ -79,5 +105,9 @@ mod tests {
         data[..4].copy_from_slice(&[0xFF, 0xD8, 0xFF, 0xE0]);
         let image = Image::from(data);
         assert_eq!(image.ext(), "jpeg");
+
+        assert_eq!(Image::from(vec![]).ext(), "image");
+        assert_eq!(Image::from(b"BM".to_vec()).ext(), "bmp");
+        assert_eq!(Image::from(b"RIFF\0\0\0\0WEB".to_vec()).ext(), "image");
     }
 }
@@ -0,0 +1,40 @@
+use crate::{audio::Type as AudioType, header::MAGIC};
+
+/// What a file handed to the decoder turns out to be, from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// An ncm file, starting with the `CTENFDAM` magic.
+    Ncm,
+    /// Audio that is not encrypted, e.g. an already converted file renamed to `.ncm`.
+    Audio(AudioType),
+    Unknown,
+}
+
+impl Input {
+    /// Classifies a file from its first [`crate::audio::SNIFF_LEN`] bytes, shorter data is fine.
+    pub fn sniff(data: &[u8]) -> Self {
+        if data.starts_with(MAGIC) {
+            return Input::Ncm;
+        }
+
+        match AudioType::sniff(data) {
+            AudioType::Unknown => Input::Unknown,
+            r#type => Input::Audio(r#type),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Input;
+    use crate::audio::Type as AudioType;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(Input::sniff(b"CTENFDAM\x01\x70"), Input::Ncm);
+        assert_eq!(Input::sniff(b"fLaC\0\0\0\x22"), Input::Audio(AudioType::Flac));
+        assert_eq!(Input::sniff(b"ID3\x04\0"), Input::Audio(AudioType::Mp3));
+        assert_eq!(Input::sniff(b"CTENFD"), Input::Unknown);
+        assert_eq!(Input::sniff(b""), Input::Unknown);
+    }
+}
@@ -1,48 +1,95 @@
-use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyInit};
-use anyhow::Result;
-use ecb::Decryptor;
+use aes::cipher::{
+    block_padding::{Pkcs7, UnpadError},
+    BlockDecryptMut, BlockEncryptMut, KeyInit,
+};
+use alloc::vec::Vec;
+use ecb::{Decryptor, Encryptor};
 
 type Aes128EcbDec = Decryptor<aes::Aes128>;
+type Aes128EcbEnc = Encryptor<aes::Aes128>;
 
 const META_KEY: &[u8; 16] = include_bytes!("meta.key");
 const CORE_KEY: &[u8; 16] = include_bytes!("core.key");
 
-pub(crate) fn decrypt_meta(data: &mut [u8]) -> Result<&[u8]> {
-    let cipher = Aes128EcbDec::new(META_KEY.into());
+/// The keys protecting the key and comment frames, the defaults are those of the official client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySet {
+    /// The AES-128 key of the key frame.
+    pub core_key: [u8; 16],
+    /// The AES-128 key of the meta in the comment frame.
+    pub meta_key: [u8; 16],
+    /// XORed into every byte of the key frame over the AES ciphertext, `100` by default.
+    pub key_xor: u8,
+    /// XORed into every byte of the comment frame, `99` by default.
+    pub comment_xor: u8,
+}
 
-    cipher.decrypt_padded_mut::<Pkcs7>(data).map_err(anyhow::Error::msg)
+impl Default for KeySet {
+    fn default() -> Self {
+        Self { core_key: *CORE_KEY, meta_key: *META_KEY, key_xor: 100, comment_xor: 99 }
+    }
 }
 
-pub(crate) fn decrypt_key(data: &mut [u8]) -> Result<&[u8]> {
-    data.iter_mut().for_each(|byte| *byte ^= 100);
+impl KeySet {
+    pub(crate) fn decrypt_meta<'a>(&self, data: &'a mut [u8]) -> Result<&'a [u8], UnpadError> {
+        let cipher = Aes128EcbDec::new(&self.meta_key.into());
+
+        cipher.decrypt_padded_mut::<Pkcs7>(data)
+    }
+
+    pub(crate) fn decrypt_key<'a>(&self, data: &'a mut [u8]) -> Result<&'a [u8], UnpadError> {
+        data.iter_mut().for_each(|byte| *byte ^= self.key_xor);
+
+        let cipher = Aes128EcbDec::new(&self.core_key.into());
+        cipher.decrypt_padded_mut::<Pkcs7>(data)
+    }
+
+    pub(crate) fn encrypt_meta(&self, data: &[u8]) -> Vec<u8> {
+        let cipher = Aes128EcbEnc::new(&self.meta_key.into());
+
+        cipher.encrypt_padded_vec_mut::<Pkcs7>(data)
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn encrypt_key(&self, data: &[u8]) -> Vec<u8> {
+        let cipher = Aes128EcbEnc::new(&self.core_key.into());
+        let mut data = cipher.encrypt_padded_vec_mut::<Pkcs7>(data);
 
-    let cipher = Aes128EcbDec::new(CORE_KEY.into());
-    cipher.decrypt_padded_mut::<Pkcs7>(data).map_err(anyhow::Error::msg)
+        data.iter_mut().for_each(|byte| *byte ^= self.key_xor);
+        data
+    }
+
+    /// XORs the comment frame, its own inverse.
+    pub(crate) fn apply_comment(&self, data: &mut [u8]) {
+        data.iter_mut().for_each(|byte| *byte ^= self.comment_xor);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{decrypt_key, decrypt_meta};
+    use super::KeySet;
     use anyhow::{Ok, Result};
     use base64::{engine::general_purpose::STANDARD as base64, Engine};
 
     #[test]
     fn test_decrypt_meta() -> Result<()> {
+        let keys = KeySet::default();
+
         let data = b"163 key(Don't modify):L64FU3W4YxX3ZFTmbZ+8/UR5O76NR+EAUgvWTIwJWpvcjmuEV52/6+VkX6oTlpPnD9GAL8pIt8cKuPSZpgbd6lm6aKAMrLJq4RSmmvJjBn/uU+CF2v+0QISaPrlQrQz+EDUnJkPGxwCM55NlSN5PzD+PgvUJhRQz0WY1aEvG8BRhYRnAHE+lx+EZnzD6UUTPbf/PnKHRhtwzcHkCE1cnoHSC1BkP7QQXkCMKtewr7mOlVhZnAviA0LDTXySmgokSrCj3oGaCLccWGRM3gkg6gPoBhlGkKRrUmOcglE63VjqP4lrj0b4WiAW/3HP2nuOCdAC2MHQFAhNFBs1Eku1qn8/KuBOZATD/BKsJDzADRt+GpwaW8dLD4N2Us1XNOGuMPESxAAyqWWLP19KExF4ZmdayT5ekodF+txkh0/U+CAfan3KtxAfbmGBdoE0HyKgpWlRdSoQ0YMQbKgMVyCE71vIERsKfTXE/54xUTW+U/4r+10jKhnHb5Ldam5r1seEYWiuNB1LpRmM3gPpUncYfA0NqqNyOh76YQTqurnkI3yDH9NfkcA5iR/ptOIZDvaefbZ3qaT7mOhrul58uWc61GfBZqEdiF77PsqVcyN8k2nr1VZqmLWyskTd0ahRkrJ5q";
-        let mut data = base64.decode(&data[22..])?;
-        let data = decrypt_meta(&mut data)?;
+        let mut data = base64.decode(&data[22..]).map_err(anyhow::Error::msg)?;
+        let data = keys.decrypt_meta(&mut data).map_err(anyhow::Error::msg)?;
         let result = r#"music:{"musicId":28254848,"musicName":"「わたしは阿良々木さんと会えたんですから」","artist":[["神前暁",14629]],"albumId":2759689,"album":"「傾物語」劇伴音楽集 & あとがたり","albumPicDocId":"109951166198486505","albumPic":"https://p3.music.126.net/P3xnM1N0Cebcs3DHSUk5QA==/109951166198486505.jpg","bitrate":320000,"mp3DocId":"885f47d55947dbaea147279f20c86c9b","duration":90331,"mvId":0,"alias":[],"transNames":[],"format":"mp3"}"# .as_bytes();
         assert_eq!(data, result);
 
         let data = b"163 key(Don't modify):L64FU3W4YxX3ZFTmbZ+8/XG3Yan7ukb4b51wQRVNwugbIH6ezOeqj930fJXsqInFzqcsHXpQ7+sTfxYhUpuytWYiHNRfULDK/Fa4CYDdIS++hoiz+fzS/vigPeEz9DVzFVCigQVQ7bU6aoNpxnTfoGH0dc14woq4w3zNbOXSV1+dPAfltLghy3ZNy+z7gOXyriEAPTrovCwuaLLKNKJ1MCmiVpyAaTFDCOIAfWHEadhZf/FGXnMM1WWDbFyAG2nezOgS4F5ods4RZ+S5PUPcI86nLB16UO/rE38WIVKbsrVmIhzUX1CwyvxWuAmA3SEvzex/uem1sKTGrsv3PG0Z2uMqMZ6LgcWu66WDTaZfmK+c+LsdmrfDV3EXBEZ4IXuLv1Vyr0+l5FBmXF/7oEfXwnOvGdUqoUItNzQrjMRnkuABE84Dhm410WVAV6KGWoGqwlbW34Qx/0CKoSx4NTiGqywQ/2I1WN4cR3HBJ5wZJTlJEYxDaYXgYJBWlJk5RaIFhXg31XVg25hKuFlVEmMSpS3PLgtEX/kJhRBwIRX/fASSe3qfVjVRRo6IHWVQEPplmJSi0Lr+CfVZymJIF/TqidD4cb4+7rDjZd8DrfWHPgG92M/2Q3llscQmdLqe7HbiM0EnwIe7E9w9H9lwBk0InO6Q6vkN9QPo64i9YbPKQdNzguu45pDh4jhr1ofTNKiGluqqS+MdX+Kv1+rMlkv8PosDD9N4T9F8F8v8OQvxQDJp4SQAVTdL6+EDDxj7nIMZ";
-        let mut data = base64.decode(&data[22..])?;
-        let data = decrypt_meta(&mut data)?;
+        let mut data = base64.decode(&data[22..]).map_err(anyhow::Error::msg)?;
+        let data = keys.decrypt_meta(&mut data).map_err(anyhow::Error::msg)?;
         let result = r#"music:{"musicId":1483150397,"musicName":"なかよし!〇!なかよし!<TVサイズ>","artist":[["水瀬いのり",1004106],["徳井青空",740113],["村川梨衣",862037]],"albumId":96108680,"album":"なかよし!〇!なかよし!<TVサイズ>","albumPicDocId":"109951165351519138","albumPic":"https://p3.music.126.net/Pu2wRB2EsQBj9LiFCUpaaQ==/109951165351519138.jpg","bitrate":128004,"mp3DocId":"82fb055351dd95380841bf290527b6e1","duration":89000,"mvId":0,"alias":["TV动画《请问您今天要来点兔子吗？ BLOOM》片尾曲"],"transNames":[],"format":"mp3"}"# .as_bytes();
         assert_eq!(data, result);
 
         let data = b"163 key(Don't modify):L64FU3W4YxX3ZFTmbZ+8/cH1s2iVmOtq9z5+98DLWLxyhvbTNVJ4gPtpzA1DOnu0LTwYkoIZ0ZOU0hcDEuMA4WEmQraNcvQyi6y71+LqG7prWWgg17gQPc/be6XbV+dDYrXq0Pmn3h62bKp6dgHv/xOfcAXP0Tje6KSXq8bXMTSBlsbr2UINd7DcJsttJX4oWN0GYzmkGKCaZJ3crOBvN15XmMHkHA6lnvz2FeoZ3Bj1tu1be7JesfCO7iI4EDph5q6BlPJRf+bTmH0STg/SHiroLNkQTPTv+0C1Dc8vhuHkUjXxsj3SG+SIAFYhKT7eOKAp1+grFGiQ4WKVIOkHh7VZ2VrXzWmxqA15W9hqo+Yfq/Uv8gj1R7n0y6jCFnbKdwH/gFsQa6bNaf98s24dPPXVgIOKjCh7k2ZFwxSg5M8LpCGNAA4x3Pymv1TjrmzjAFLEcLRIrTqBqlT2LELPQhmXm5+kTV6SbIAv88u+opztVl9Iz7CPgifKJPcLZ/yb4pt6BJBKBhCi6gzig7CllsA99W3S3YteoCWiaKh1dtSz3OURqk22sObLKAD8X9EDul4hfw4QXWNcGe059utso0vDkdkNYhz4uTW38PfuFBv9TLwINTiJpfI2i9+35Yh8ab78Ua7L/H6xjA2/x3fxHaywi4SypxwQOc9S/+QUi9s=";
-        let mut data = base64.decode(&data[22..])?;
-        let data = decrypt_meta(&mut data)?;
+        let mut data = base64.decode(&data[22..]).map_err(anyhow::Error::msg)?;
+        let data = keys.decrypt_meta(&mut data).map_err(anyhow::Error::msg)?;
         let result = r#"music:{"musicId":1483146611,"musicName":"天空カフェテリア<TVサイズ>","artist":[["Petit Rabbit's",939023]],"albumId":96107824,"album":"天空カフェテリア<TVサイズ>","albumPicDocId":"109951165351498889","albumPic":"https://p4.music.126.net/eWfBZq8nguEwX_mBAzdh6Q==/109951165351498889.jpg","bitrate":128004,"mp3DocId":"26d870a21a2e995c657c9882df670789","duration":89000,"mvId":0,"alias":["TV动画《请问您今天要来点兔子吗？ BLOOM》片头曲"],"transNames":[],"format":"mp3"}"# .as_bytes();
         assert_eq!(data, result);
 
@@ -51,6 +98,8 @@ mod tests {
 
     #[test]
     fn test_decrypt_key() -> Result<()> {
+        let keys = KeySet::default();
+
         let mut data = [
             44, 206, 213, 235, 105, 234, 251, 20, 85, 13, 69, 191, 97, 221, 23, 29, 18, 21, 246,
             51, 21, 69, 239, 181, 244, 6, 32, 104, 211, 135, 179, 202, 61, 32, 50, 218, 231, 3, 85,
@@ -60,7 +109,7 @@ mod tests {
             109, 247, 164, 233, 37, 82, 40, 214, 0, 159, 5, 178, 114, 243, 218, 126, 60, 20, 5,
             164, 198, 166, 244, 88, 15, 95, 132, 197, 175, 252, 215, 77, 30,
         ];
-        let data = decrypt_key(&mut data)?;
+        let data = keys.decrypt_key(&mut data).map_err(anyhow::Error::msg)?;
         let result = b"neteasecloudmusic143621215014397E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb";
         assert_eq!(data, result);
 
@@ -74,7 +123,7 @@ mod tests {
             29, 231, 137, 92, 68, 121, 212, 12, 108, 112, 23, 163, 14, 252, 18, 154, 195, 161, 219,
             220, 182, 182, 222, 155, 40, 178, 65, 51, 212,
         ];
-        let data = decrypt_key(&mut data)?;
+        let data = keys.decrypt_key(&mut data).map_err(anyhow::Error::msg)?;
         let result = b"neteasecloudmusic20760577722136161195829593819E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb";
         assert_eq!(data, result);
 
@@ -88,10 +137,24 @@ mod tests {
             30, 57, 75, 250, 29, 176, 0, 59, 216, 67, 169, 114, 110, 128, 82, 47, 10, 156, 9, 66,
             164, 56, 90, 51, 125, 4, 184, 133, 86, 188, 99, 103,
         ];
-        let data = decrypt_key(&mut data)?;
+        let data = keys.decrypt_key(&mut data).map_err(anyhow::Error::msg)?;
         let result = b"neteasecloudmusic7759998725616604731636941146E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb";
         assert_eq!(data, result);
 
+        Ok(())
+    }
+    #[test]
+    fn test_encrypt_roundtrip() -> Result<()> {
+        let keys = KeySet::default();
+
+        let data = b"neteasecloudmusic143621215014397E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb";
+        let mut encrypted = keys.encrypt_key(data);
+        assert_eq!(keys.decrypt_key(&mut encrypted).map_err(anyhow::Error::msg)?, data);
+
+        let data = br#"music:{"musicId":28254848,"format":"mp3"}"#;
+        let mut encrypted = keys.encrypt_meta(data);
+        assert_eq!(keys.decrypt_meta(&mut encrypted).map_err(anyhow::Error::msg)?, data);
+
         Ok(())
     }
 }
@@ -0,0 +1,38 @@
+use crate::audio::Cipher;
+
+/// The cipher of the audio data, which can decrypt any byte range on its own.
+///
+/// It is cheap to clone and can be shared between threads,
+/// e.g. to decrypt the chunks of HTTP range requests in parallel.
+#[derive(Debug, Clone)]
+pub struct KeyStream {
+    cipher: Cipher,
+}
+
+impl KeyStream {
+    /// Builds the key stream from the decrypted key, i.e. [`crate::decoder::Decoder::key`].
+    pub fn new(key: &[u8]) -> Self {
+        Self { cipher: Cipher::ncm(key) }
+    }
+
+    /// XORs `buf` with the key stream, `offset` being the offset of `buf` from the start of the audio data.
+    ///
+    /// Applying it twice restores the input, so it encrypts as well as decrypts.
+    pub fn apply(&self, offset: u64, buf: &mut [u8]) {
+        self.cipher.apply(offset, buf);
+    }
+
+    /// [`KeyStream::apply`], splitting `buf` into chunks decrypted on the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn apply_parallel(&self, offset: u64, buf: &mut [u8]) {
+        self.cipher.apply_parallel(offset, buf);
+    }
+}
+
+/// Decrypts a whole audio payload in place, in parallel on the rayon thread pool.
+///
+/// `buf` must start at the beginning of the audio data, `key` is the decrypted key.
+#[cfg(feature = "rayon")]
+pub fn decrypt_parallel(buf: &mut [u8], key: &[u8]) {
+    KeyStream::new(key).apply_parallel(0, buf);
+}
@@ -0,0 +1,56 @@
+/// A range of bytes in an ncm file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Span {
+    /// The offset just past the span.
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Where every part of an ncm file sits, see [`crate::parser::NcmParser::layout`].
+///
+/// Parts the parser has not reached, e.g. in a truncated file, are left empty at offset `0`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// The `CTENFDAM` magic.
+    pub magic: Span,
+    /// The two version bytes after the magic.
+    pub version: Span,
+    /// The key frame, including its length field.
+    pub key: Span,
+    /// The comment frame, including its length field.
+    pub comment: Span,
+    /// The CRC32 of everything before it.
+    pub crc: Span,
+    /// The byte after the CRC32.
+    pub gap: Span,
+    /// The cover frame, including both length fields, the image and the padding after it.
+    pub cover: Span,
+    /// The image data in the cover frame.
+    pub image: Span,
+    /// The audio, its length is only known if the length of the input is,
+    /// e.g. with [`crate::decoder::Decoder::probe`] and [`crate::decoder::Decoder::from_slice`], and `0` otherwise.
+    pub audio: Span,
+}
+
+impl Layout {
+    /// The parts with their names, in file order.
+    pub fn parts(&self) -> [(&'static str, Span); 9] {
+        [
+            ("magic", self.magic),
+            ("version", self.version),
+            ("key", self.key),
+            ("comment", self.comment),
+            ("crc", self.crc),
+            ("gap", self.gap),
+            ("cover", self.cover),
+            ("image", self.image),
+            ("audio", self.audio),
+        ]
+    }
+}
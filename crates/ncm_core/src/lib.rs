@@ -1,5 +1,31 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "async")]
+pub mod async_decoder;
 pub mod audio;
+mod crc32;
 pub mod decoder;
+#[cfg(feature = "std")]
+pub mod encoder;
+mod error;
+#[cfg(feature = "std")]
+pub mod format;
+pub mod header;
 pub mod image;
-mod key;
+pub mod input;
+pub mod key;
+pub mod key_stream;
+pub mod layout;
+pub mod meta;
+pub mod meta_key;
 mod ncm_rc4;
+pub mod options;
+pub mod parser;
+pub mod qmc;
+pub mod recovery;
+pub mod source;
+pub mod view;
+
+pub use error::{Error, Frame, Result};
@@ -0,0 +1,35 @@
+use alloc::vec::Vec;
+
+/// The meta stored in the comment frame, as returned by [`crate::meta_key::decode_163_key`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Meta {
+    pub kind: MetaKind,
+    /// The json after the `music:` or `dj:` prefix.
+    pub json: Vec<u8>,
+}
+
+/// What the meta json describes, told apart by the prefix of the decrypted meta.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetaKind {
+    /// A song, `music:` followed by the track json.
+    #[default]
+    Music,
+    /// A radio or DJ program, `dj:` followed by the program json with the track under `mainMusic`.
+    Dj,
+}
+
+impl MetaKind {
+    pub fn prefix(&self) -> &'static [u8] {
+        match self {
+            MetaKind::Music => b"music:",
+            MetaKind::Dj => b"dj:",
+        }
+    }
+
+    /// Splits the decrypted meta into its kind and the json after the prefix.
+    pub fn strip(meta: &[u8]) -> Option<(Self, &[u8])> {
+        [MetaKind::Music, MetaKind::Dj]
+            .into_iter()
+            .find_map(|kind| Some((kind, meta.strip_prefix(kind.prefix())?)))
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{
+    error::{Error, Result},
+    key::KeySet,
+    meta::{Meta, MetaKind},
+};
+use alloc::string::String;
+use base64::{engine::general_purpose::STANDARD as base64, Engine};
+
+/// The prefix of the comment of ncm files, also left in the tags of converted files.
+pub const PREFIX: &str = "163 key(Don't modify):";
+
+/// Decodes a `163 key(Don't modify):` string back into its meta.
+///
+/// Surrounding whitespace is ignored, as tag editors tend to add it.
+pub fn decode_163_key(value: &str) -> Result<Meta> {
+    decode_163_key_with(value, &KeySet::default())
+}
+
+/// [`decode_163_key`] with the meta key of `keys`.
+pub fn decode_163_key_with(value: &str, keys: &KeySet) -> Result<Meta> {
+    decode(value.trim().as_bytes(), 0, keys)
+}
+
+/// Encodes `meta` into a `163 key(Don't modify):` string, the inverse of [`decode_163_key`].
+pub fn encode_163_key(meta: &Meta) -> String {
+    encode_163_key_with(meta, &KeySet::default())
+}
+
+/// [`encode_163_key`] with the meta key of `keys`.
+pub fn encode_163_key_with(meta: &Meta, keys: &KeySet) -> String {
+    let data = keys.encrypt_meta(&[meta.kind.prefix(), &meta.json].concat());
+
+    let mut value = String::from(PREFIX);
+    base64.encode_string(data, &mut value);
+    value
+}
+
+/// Decodes the plain text comment of the ncm file at `offset`.
+pub(crate) fn decode(comment: &[u8], offset: u64, keys: &KeySet) -> Result<Meta> {
+    let data = comment.strip_prefix(PREFIX.as_bytes()).ok_or(Error::InvalidComment { offset })?;
+    let mut data = base64.decode(data).map_err(|source| Error::Base64 { offset, source })?;
+
+    let data = keys.decrypt_meta(&mut data).map_err(|_| Error::Padding { offset })?;
+    let (kind, json) = MetaKind::strip(data).ok_or(Error::InvalidMeta { offset })?;
+
+    Ok(Meta { kind, json: json.to_vec() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_163_key, encode_163_key};
+    use crate::{
+        meta::{Meta, MetaKind},
+        Error,
+    };
+    use alloc::format;
+
+    #[test]
+    fn test_163_key() {
+        for kind in [MetaKind::Music, MetaKind::Dj] {
+            let meta = Meta { kind, json: br#"{"musicId":28254848,"format":"mp3"}"#.to_vec() };
+
+            let value = encode_163_key(&meta);
+            assert!(value.starts_with("163 key(Don't modify):"));
+            assert_eq!(decode_163_key(&value).unwrap(), meta);
+            assert_eq!(decode_163_key(&format!(" {value}\n")).unwrap(), meta);
+        }
+
+        assert!(matches!(decode_163_key("music:{}"), Err(Error::InvalidComment { offset: 0 })));
+        assert!(matches!(decode_163_key("163 key(Don't modify):!"), Err(Error::Base64 { .. })));
+        assert!(matches!(decode_163_key("163 key(Don't modify):AAAA"), Err(Error::Padding { .. })));
+    }
+}
@@ -1,17 +1,30 @@
+/// The key stream repeats every 256 bytes, it is stored twice so that
+/// any run of up to 256 bytes is a contiguous slice of `state`.
 #[derive(Debug, Clone)]
 pub(crate) struct NcmRc4 {
-    state: [u8; 256],
+    state: [u8; 512],
 }
 
 impl NcmRc4 {
     pub fn new(key: &[u8]) -> Self {
-        let mut rc4 = NcmRc4 { state: [0; 256] };
+        let mut rc4 = NcmRc4 { state: [0; 512] };
 
         rc4.ncm_prga(&Self::ksa(key));
 
         rc4
     }
 
+    /// XORs `buf` with the key stream, `position` being the offset of `buf` in the audio.
+    pub fn apply(&self, position: u64, buf: &mut [u8]) {
+        let start = (position % 256) as usize;
+
+        // Equal length slices let the compiler vectorize the XOR.
+        buf.chunks_mut(256).for_each(|chunk| {
+            let key = &self.state[start..start + chunk.len()];
+            chunk.iter_mut().zip(key).for_each(|(byte, x)| *byte ^= x);
+        });
+    }
+
     fn ksa(key: &[u8]) -> [u8; 256] {
         let mut state = [0; 256];
 
@@ -39,14 +52,29 @@ impl NcmRc4 {
             let index = state[key1 as usize].wrapping_add(state[key2 as usize]);
             self.state[i as usize] = state[index as usize];
         });
+
+        let (head, tail) = self.state.split_at_mut(256);
+        tail.copy_from_slice(head);
     }
 }
 
-impl IntoIterator for NcmRc4 {
-    type Item = u8;
-    type IntoIter = std::array::IntoIter<u8, 256>;
+#[cfg(test)]
+mod tests {
+    use super::NcmRc4;
+
+    #[test]
+    fn test_apply() {
+        let rc4 = NcmRc4::new(
+            b"E7fT49x7dof9OKCgg9cdvhEuezy3iZCL1nFvBFd1T4uSktAJKmwZXsijPbijliionVUXXg9plTbXEclAE9Lb",
+        );
+
+        let mut expected = [0; 1000];
+        expected.iter_mut().zip(rc4.state[..256].iter().cycle()).for_each(|(byte, x)| *byte = *x);
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.state.into_iter()
+        for position in [0, 1, 255, 256, 300] {
+            let mut buf = [0; 700];
+            rc4.apply(position, &mut buf);
+            assert_eq!(buf, expected[position as usize % 256..][..700]);
+        }
     }
 }
@@ -0,0 +1,39 @@
+use crate::key::KeySet;
+
+/// Options for [`crate::decoder::Decoder::decode_with`] and [`crate::parser::NcmParser::with_options`].
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Whether to read the cover image, `true` by default.
+    pub load_image: bool,
+    /// Whether to check the stored CRC32 against the key and comment frames, `false` by default.
+    pub verify_crc: bool,
+    /// Whether to keep what could be read from an input which ends before the audio,
+    /// instead of failing, `false` by default. The key frame must still be complete.
+    pub lenient: bool,
+    /// Whether [`crate::decoder::Decoder::from_slice_with`] searches for the audio when the cover frame lengths
+    /// are corrupt, scanning up to `max_image_len` bytes from the start of the image, `false` by default.
+    pub recover: bool,
+    /// The largest key frame accepted, 4 KiB by default.
+    pub max_key_len: u64,
+    /// The largest comment frame accepted, 1 MiB by default.
+    pub max_comment_len: u64,
+    /// The largest image frame accepted, 32 MiB by default, checked even if the image is not loaded.
+    pub max_image_len: u64,
+    /// The keys of the key and comment frames, for clients which ship their own.
+    pub keys: KeySet,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            load_image: true,
+            verify_crc: false,
+            lenient: false,
+            recover: false,
+            max_key_len: 0x1000,
+            max_comment_len: 0x10_0000,
+            max_image_len: 0x200_0000,
+            keys: KeySet::default(),
+        }
+    }
+}
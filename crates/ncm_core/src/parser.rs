@@ -0,0 +1,355 @@
+use crate::{
+    crc32::Crc32,
+    error::{Error, Frame, Result},
+    header::{Header, MAGIC},
+    image::Image,
+    layout::{Layout, Span},
+    meta::MetaKind,
+    meta_key,
+    ncm_rc4::NcmRc4,
+    options::DecodeOptions,
+};
+use alloc::{vec, vec::Vec};
+
+/// Parts of an ncm file, emitted by [`NcmParser::feed`] in file order.
+#[derive(Debug)]
+pub enum Event {
+    /// The decrypted key, without the `neteasecloudmusic` prefix.
+    Key(Vec<u8>),
+    /// The plain text comment, only emitted if the file has one.
+    Comment(Vec<u8>),
+    /// The meta json, without the `music:` or `dj:` prefix, only emitted if the file has one.
+    Meta(MetaKind, Vec<u8>),
+    /// The version, CRC32 and gap fields, emitted after the CRC32 has been verified if requested.
+    Header(Header),
+    /// The cover image, only emitted if the file has one.
+    Image(Image),
+    /// A chunk of decrypted audio.
+    AudioChunk(Vec<u8>),
+}
+
+/// Where and by how much an input fell short, see [`NcmParser::finish_truncated`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+    /// The offset at which the input ended.
+    pub offset: u64,
+    /// The bytes missing to complete the field or frame, and the padding after the image,
+    /// as declared by their lengths. The length of the audio is not declared, so it is not included.
+    pub missing: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Magic,
+    KeyLen,
+    Key,
+    CommentLen,
+    Comment,
+    Checksum,
+    CoverLen,
+    ImageLen,
+    Image,
+    Padding,
+    Audio,
+}
+
+/// A push parser for ncm files which does no I/O.
+///
+/// Bytes are passed in as they arrive through [`NcmParser::feed`], in chunks of any size,
+/// and the parser returns the [`Event`]s completed by each chunk.
+/// After an error the parser must not be fed again.
+#[derive(Debug)]
+pub struct NcmParser {
+    options: DecodeOptions,
+    state: State,
+    position: u64,
+    frame_offset: u64,
+    remaining: u64,
+    buffer: Vec<u8>,
+    header: Header,
+    crc: Crc32,
+    cover_len: u64,
+    image_offset: u64,
+    image_len: u64,
+    rc4: Option<NcmRc4>,
+    audio_offset: u64,
+    layout: Layout,
+}
+
+impl Default for NcmParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NcmParser {
+    pub fn new() -> Self {
+        Self::with_options(DecodeOptions::default())
+    }
+
+    pub fn with_options(options: DecodeOptions) -> Self {
+        Self {
+            options,
+            state: State::Magic,
+            position: 0,
+            frame_offset: 0,
+            remaining: 10,
+            buffer: vec![],
+            header: Header::default(),
+            crc: Crc32::default(),
+            cover_len: 0,
+            image_offset: 0,
+            image_len: 0,
+            rc4: None,
+            audio_offset: 0,
+            layout: Layout::default(),
+        }
+    }
+
+    pub fn feed(&mut self, mut data: &[u8]) -> Result<Vec<Event>> {
+        let mut events = vec![];
+
+        while !data.is_empty() {
+            if self.state == State::Audio {
+                let mut chunk = data.to_vec();
+                if let Some(rc4) = &self.rc4 {
+                    rc4.apply(self.position - self.audio_offset, &mut chunk);
+                }
+                self.position += chunk.len() as u64;
+                events.push(Event::AudioChunk(chunk));
+                break;
+            }
+
+            let size = data.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+            if !self.skippable() {
+                self.buffer.extend_from_slice(&data[..size]);
+            }
+            data = &data[size..];
+            self.position += size as u64;
+            self.remaining -= size as u64;
+
+            self.advance_all(&mut events)?;
+        }
+
+        Ok(events)
+    }
+
+    /// Skips `len` bytes of the current field without feeding them.
+    ///
+    /// Only valid while [`NcmParser::skippable`] holds and for at most [`NcmParser::wanted`] bytes.
+    pub fn skip(&mut self, len: u64) -> Result<Vec<Event>> {
+        assert!(self.skippable() && len <= self.remaining, "cannot skip {len} bytes here");
+
+        let mut events = vec![];
+
+        self.position += len;
+        self.remaining -= len;
+        self.advance_all(&mut events)?;
+
+        Ok(events)
+    }
+
+    /// Whether the bytes of the current field are discarded,
+    /// so a seekable reader may [`NcmParser::skip`] them instead.
+    pub fn skippable(&self) -> bool {
+        match self.state {
+            State::Padding => true,
+            State::Image => !self.options.load_image,
+            _ => false,
+        }
+    }
+
+    /// Signals the end of input, failing if it ended before the audio.
+    pub fn finish(&self) -> Result<()> {
+        if self.state == State::Audio {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedEof { offset: self.frame_offset })
+        }
+    }
+
+    /// Ends an input which stopped before the audio, keeping what was complete.
+    ///
+    /// Emits the part of the image read so far, and fails unless the key frame was complete.
+    pub fn finish_truncated(&mut self) -> Result<(Vec<Event>, Truncation)> {
+        if self.state <= State::Key {
+            return Err(Error::UnexpectedEof { offset: self.frame_offset });
+        }
+
+        let mut events = vec![];
+        let mut missing = self.remaining;
+        if self.state == State::Image {
+            missing += self.cover_len;
+            if !self.buffer.is_empty() {
+                events.push(Event::Image(core::mem::take(&mut self.buffer).into()));
+            }
+        }
+
+        Ok((events, Truncation { offset: self.position, missing }))
+    }
+
+    /// The number of bytes needed to complete the current field, `0` once the parser is in the audio.
+    ///
+    /// Feeding exactly this many bytes never reads ahead into the audio,
+    /// which lets a pull-based reader hand the rest of its input to [`crate::audio::Audio`].
+    pub fn wanted(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Whether the header is complete and the rest of the input is audio.
+    pub fn is_audio(&self) -> bool {
+        self.state == State::Audio
+    }
+
+    /// The number of bytes fed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The offset of the field or frame currently being parsed.
+    pub fn offset(&self) -> u64 {
+        self.frame_offset
+    }
+
+    /// The offset of the image data, valid once the image frame has been reached.
+    pub fn image_offset(&self) -> u64 {
+        self.image_offset
+    }
+
+    /// The length of the image data, valid once the image frame has been reached.
+    pub fn image_len(&self) -> u64 {
+        self.image_len
+    }
+
+    /// Where the parts parsed so far sit in the input.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    fn advance_all(&mut self, events: &mut Vec<Event>) -> Result<()> {
+        while self.remaining == 0 && self.state != State::Audio {
+            self.advance(events)?;
+        }
+
+        Ok(())
+    }
+
+    fn advance(&mut self, events: &mut Vec<Event>) -> Result<()> {
+        let offset = self.frame_offset;
+        let buffer = core::mem::take(&mut self.buffer);
+
+        if self.options.verify_crc && self.state < State::Checksum {
+            self.crc.update(&buffer);
+        }
+
+        let (state, remaining) = match self.state {
+            State::Magic => {
+                if &buffer[..8] != MAGIC {
+                    return Err(Error::HeaderMismatch { offset });
+                }
+                self.header.version = [buffer[8], buffer[9]];
+                self.layout.magic = Span { offset, len: 8 };
+                self.layout.version = Span { offset: offset + 8, len: 2 };
+                (State::KeyLen, 4)
+            }
+            State::KeyLen => {
+                let len = self.limit(Frame::Key, Self::len(&buffer), self.options.max_key_len)?;
+                self.layout.key = Span { offset, len: 4 + len };
+                (State::Key, len)
+            }
+            State::Key => {
+                let mut key = buffer;
+                let key = self
+                    .options
+                    .keys
+                    .decrypt_key(&mut key)
+                    .map_err(|_| Error::Padding { offset })?;
+                let key =
+                    key.strip_prefix(b"neteasecloudmusic").ok_or(Error::InvalidKey { offset })?;
+
+                self.rc4 = Some(NcmRc4::new(key));
+                events.push(Event::Key(key.to_vec()));
+                (State::CommentLen, 4)
+            }
+            State::CommentLen => {
+                let limit = self.options.max_comment_len;
+                let len = self.limit(Frame::Comment, Self::len(&buffer), limit)?;
+                self.layout.comment = Span { offset, len: 4 + len };
+                (State::Comment, len)
+            }
+            State::Comment => {
+                let mut comment = buffer;
+                if !comment.is_empty() {
+                    self.options.keys.apply_comment(&mut comment);
+
+                    let meta = meta_key::decode(&comment, offset, &self.options.keys)?;
+                    events.push(Event::Comment(comment));
+                    events.push(Event::Meta(meta.kind, meta.json));
+                }
+                (State::Checksum, 5)
+            }
+            State::Checksum => {
+                self.header.crc = Self::len(&buffer) as u32;
+                self.header.gap = buffer[4];
+                self.layout.crc = Span { offset, len: 4 };
+                self.layout.gap = Span { offset: offset + 4, len: 1 };
+
+                let actual = self.crc.finish();
+                if self.options.verify_crc && actual != self.header.crc {
+                    return Err(Error::CrcMismatch { offset, expected: self.header.crc, actual });
+                }
+
+                events.push(Event::Header(self.header));
+                (State::CoverLen, 4)
+            }
+            State::CoverLen => {
+                self.cover_len = Self::len(&buffer);
+                self.layout.cover = Span { offset, len: 8 + self.cover_len };
+                (State::ImageLen, 4)
+            }
+            State::ImageLen => {
+                let img_len =
+                    self.limit(Frame::Image, Self::len(&buffer), self.options.max_image_len)?;
+                self.cover_len = self.cover_len.saturating_sub(img_len);
+                self.image_offset = self.position;
+                self.image_len = img_len;
+                self.layout.cover.len = 8 + img_len + self.cover_len;
+                self.layout.image = Span { offset: self.position, len: img_len };
+                (State::Image, img_len)
+            }
+            State::Image => {
+                if !buffer.is_empty() {
+                    events.push(Event::Image(buffer.into()));
+                }
+                (State::Padding, self.cover_len)
+            }
+            State::Padding => {
+                self.audio_offset = self.position;
+                self.layout.audio = Span { offset: self.position, len: 0 };
+                (State::Audio, 0)
+            }
+            State::Audio => unreachable!(),
+        };
+
+        if !matches!(state, State::Key | State::Comment | State::Image) {
+            self.frame_offset = self.position;
+        }
+        self.state = state;
+        self.remaining = remaining;
+
+        Ok(())
+    }
+
+    /// Checks the length of a frame before anything is buffered for it.
+    fn limit(&self, frame: Frame, len: u64, limit: u64) -> Result<u64> {
+        if len > limit {
+            return Err(Error::FrameTooLarge { offset: self.frame_offset, frame, len, limit });
+        }
+
+        Ok(len)
+    }
+
+    fn len(buffer: &[u8]) -> u64 {
+        u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as u64
+    }
+}
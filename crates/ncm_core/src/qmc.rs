@@ -0,0 +1,204 @@
+#[cfg(feature = "async")]
+use crate::async_decoder::AsyncAudio;
+use crate::audio::{Type as AudioType, SNIFF_LEN};
+#[cfg(feature = "std")]
+use crate::{
+    audio::{Audio, MIN_AUDIO_LEN},
+    error::{Error, Result},
+    format::{Confidence, DecodedStream, EncryptedFormat},
+    options::DecodeOptions,
+};
+#[cfg(feature = "std")]
+use std::{boxed::Box, io::Read, vec};
+
+/// The static mask of QMCv1, indexed by the offset in the audio.
+const MASK: [u8; 128] = [
+    0xC3, 0x4A, 0xD6, 0xCA, 0x90, 0x67, 0xF7, 0x52, 0xD8, 0xA1, 0x66, 0x62, 0x9F, 0x5B, 0x09, 0x00,
+    0xC3, 0x5E, 0x95, 0x23, 0x9F, 0x13, 0x11, 0x7E, 0xD8, 0x92, 0x3F, 0xBC, 0x90, 0xBB, 0x74, 0x0E,
+    0xC3, 0x47, 0x74, 0x3D, 0x90, 0xAA, 0x3F, 0x51, 0xD8, 0xF4, 0x11, 0x84, 0x9F, 0xDE, 0x95, 0x1D,
+    0xC3, 0xC6, 0x09, 0xD5, 0x9F, 0xFA, 0x66, 0xF9, 0xD8, 0xF0, 0xF7, 0xA0, 0x90, 0xA1, 0xD6, 0xF3,
+    0xC3, 0xF3, 0xD6, 0xA1, 0x90, 0xA0, 0xF7, 0xF0, 0xD8, 0xF9, 0x66, 0xFA, 0x9F, 0xD5, 0x09, 0xC6,
+    0xC3, 0x1D, 0x95, 0xDE, 0x9F, 0x84, 0x11, 0xF4, 0xD8, 0x51, 0x3F, 0xAA, 0x90, 0x3D, 0x74, 0x47,
+    0xC3, 0x0E, 0x74, 0xBB, 0x90, 0xBC, 0x3F, 0x92, 0xD8, 0x7E, 0x11, 0x13, 0x9F, 0x23, 0x95, 0x5E,
+    0xC3, 0x00, 0x09, 0x5B, 0x9F, 0x62, 0x66, 0xA1, 0xD8, 0x52, 0xF7, 0x67, 0x90, 0xCA, 0xD6, 0x4A,
+];
+
+/// The mask restarts every `0x7FFF` bytes, not every `0x8000`.
+const MASK_PERIOD: u64 = 0x7FFF;
+
+/// Decrypts a QMCv1 file, i.e. `.qmc0`, `.qmc3`, `.qmcflac` or `.qmcogg`, which is the whole
+/// audio XORed with a static mask, without a header, meta or cover.
+#[cfg(feature = "std")]
+pub fn decode<R>(input: R) -> Result<Audio<R>>
+where
+    R: Read,
+{
+    Audio::try_new_qmc(input, MIN_AUDIO_LEN).map_err(|err| Error::io(0, err))
+}
+
+/// The async counterpart of [`decode`], for tokio readers.
+#[cfg(feature = "async")]
+pub async fn decode_async<R>(input: R) -> Result<AsyncAudio<R>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    AsyncAudio::try_new_qmc(input, MIN_AUDIO_LEN).await.map_err(|err| Error::io(0, err))
+}
+
+/// XORs `buf` with the mask, `position` being the offset of `buf` in the audio.
+///
+/// Applying it twice restores the input, so it encrypts as well as decrypts.
+pub fn apply(position: u64, buf: &mut [u8]) {
+    let mut offset = position;
+    let mut rest = buf;
+    while !rest.is_empty() {
+        // the mask index runs up to 0x7FFF once, then from 0 to 0x7FFE over and over
+        let (start, end) = if offset <= MASK_PERIOD {
+            (offset, MASK_PERIOD + 1)
+        } else {
+            (offset % MASK_PERIOD, MASK_PERIOD)
+        };
+        let len = ((end - start) as usize).min(rest.len());
+        let (segment, tail) = rest.split_at_mut(len);
+
+        let mask = MASK.iter().cycle().skip(start as usize % MASK.len());
+        segment.iter_mut().zip(mask).for_each(|(byte, mask)| *byte ^= mask);
+
+        offset += len as u64;
+        rest = tail;
+    }
+}
+
+/// Whether the first bytes of an input decrypt to a known container.
+pub fn sniff(head: &[u8]) -> AudioType {
+    let mut buf = head[..head.len().min(SNIFF_LEN)].to_vec();
+    apply(0, &mut buf);
+    AudioType::sniff(&buf)
+}
+
+/// The QMCv1 format, which has no magic, so it is recognised by the audio it decrypts to.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Qmc;
+
+#[cfg(feature = "std")]
+impl EncryptedFormat for Qmc {
+    fn name(&self) -> &'static str {
+        "qmc"
+    }
+
+    fn sniff(&self, head: &[u8]) -> Confidence {
+        match sniff(head) {
+            AudioType::Unknown => Confidence::No,
+            _ => Confidence::Maybe,
+        }
+    }
+
+    fn open<'a>(
+        &self,
+        input: Box<dyn Read + 'a>,
+        options: &DecodeOptions,
+    ) -> Result<DecodedStream<'a>> {
+        let min_len = if options.lenient { 0 } else { MIN_AUDIO_LEN };
+        let audio = Audio::try_new_qmc(input, min_len).map_err(|err| Error::io(0, err))?;
+
+        Ok(DecodedStream {
+            audio_type: audio.r#type(),
+            audio: Box::new(audio),
+            key: vec![],
+            comment: vec![],
+            meta: None,
+            cover: None,
+            layout: None,
+            truncated: None,
+            recovered: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, sniff, MASK, MASK_PERIOD};
+    use crate::audio::Type as AudioType;
+    use alloc::vec;
+
+    /// The mask byte at `offset`, one at a time.
+    fn mask(offset: u64) -> u8 {
+        let offset = if offset > MASK_PERIOD { offset % MASK_PERIOD } else { offset };
+        MASK[(offset % MASK.len() as u64) as usize]
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(
+            [mask(0), mask(1), mask(8), mask(127), mask(128)],
+            [0xC3, 0x4A, 0xD8, 0x4A, 0xC3]
+        );
+        // the mask wraps at 0x7FFF, so 0x8000 continues like 1
+        assert_eq!(mask(0x7FFF), mask(127));
+        assert_eq!(mask(0x8000), mask(1));
+        assert_eq!(mask(0x7FFF * 2 + 5), mask(5));
+
+        let mut buf = vec![0; 0x20000];
+        apply(0, &mut buf);
+        assert!(buf.iter().zip(0..).all(|(&byte, offset)| byte == mask(offset)));
+
+        for position in [1, 127, 0x7FF0, 0x7FFF, 0x8000, 0xFFFD, 0xFFFE, 0x17FF0] {
+            let mut chunk = vec![0; 0x100];
+            apply(position, &mut chunk);
+            let start = position as usize;
+            assert_eq!(chunk, buf[start..start + 0x100], "{position:#x}");
+        }
+    }
+
+    /// Bytes from the seed map walk of qmcdump, which the table above was not derived from.
+    #[test]
+    fn test_known_answer() {
+        let mut head = *b"fLaC\0\0\0\x22\x12\0\x12\0\0\0\x0e\0";
+        apply(0, &mut head);
+        assert_eq!(
+            head,
+            [
+                0xA5, 0x06, 0xB7, 0x89, 0x90, 0x67, 0xF7, 0x70, 0xCA, 0xA1, 0x74, 0x62, 0x9F, 0x5B,
+                0x07, 0x00
+            ]
+        );
+
+        let cases: [(u64, [u8; 16]); 3] = [
+            (
+                0x7FF8,
+                [
+                    0xD8, 0x52, 0xF7, 0x67, 0x90, 0xCA, 0xD6, 0x4A, 0x4A, 0xD6, 0xCA, 0x90, 0x67,
+                    0xF7, 0x52, 0xD8,
+                ],
+            ),
+            (
+                0xFFF8,
+                [
+                    0x52, 0xF7, 0x67, 0x90, 0xCA, 0xD6, 0xC3, 0x4A, 0xD6, 0xCA, 0x90, 0x67, 0xF7,
+                    0x52, 0xD8, 0xA1,
+                ],
+            ),
+            (
+                0x17FF0,
+                [
+                    0x09, 0x5B, 0x9F, 0x62, 0x66, 0xA1, 0xD8, 0x52, 0xF7, 0x67, 0x90, 0xCA, 0xD6,
+                    0xC3, 0x4A, 0xD6,
+                ],
+            ),
+        ];
+        for (position, expected) in cases {
+            let mut buf = [0; 16];
+            apply(position, &mut buf);
+            assert_eq!(buf, expected, "{position:#x}");
+        }
+    }
+
+    #[test]
+    fn test_sniff() {
+        let mut head = b"fLaC\0\0\0\x22".to_vec();
+        apply(0, &mut head);
+        assert_eq!(sniff(&head), AudioType::Flac);
+        assert_eq!(sniff(b"fLaC\0\0\0\x22"), AudioType::Unknown);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{audio::Type as AudioType, key_stream::KeyStream};
+use core::ops::Range;
+
+/// How many bytes are decrypted at each candidate offset, enough for every signature [`AudioType::sniff`] knows
+/// apart from the codec inside Ogg.
+const PROBE_LEN: usize = 16;
+
+/// Where [`crate::decoder::Decoder::from_slice`] found the audio after the frame lengths turned out to be corrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    /// The audio offset the cover frame lengths pointed at, possibly past the end of the input.
+    pub declared_offset: u64,
+    /// The offset at which a known container signature was found.
+    pub audio_offset: u64,
+}
+
+/// Finds the first offset in `range` of `data` at which the audio decrypts to a known container signature.
+///
+/// Bare MPEG and ADTS frame headers are skipped, as a two byte sync word turns up
+/// in random data every few kilobytes, leaving the four byte and longer magics.
+pub fn find_audio(
+    data: &[u8],
+    key_stream: &KeyStream,
+    range: Range<usize>,
+) -> Option<(usize, AudioType)> {
+    let end = range.end.min(data.len().saturating_sub(PROBE_LEN - 1));
+
+    (range.start..end).find_map(|offset| {
+        let mut buf = [0; PROBE_LEN];
+        buf.copy_from_slice(&data[offset..offset + PROBE_LEN]);
+        key_stream.apply(0, &mut buf);
+
+        match AudioType::sniff(&buf) {
+            _ if buf[0] == 0xFF => None,
+            AudioType::Unknown => None,
+            AudioType::Mp3 if !is_id3_header(&buf) => None,
+            r#type => Some((offset, r#type)),
+        }
+    })
+}
+
+/// Whether `buf` starts with a whole ID3v2 header rather than just the three letters: a known
+/// major version, a revision and a size of four synchsafe bytes.
+fn is_id3_header(buf: &[u8]) -> bool {
+    match buf {
+        [b'I', b'D', b'3', major, revision, _flags, size @ ..] if size.len() >= 4 => {
+            (2..=4).contains(major)
+                && *revision != 0xFF
+                && size[..4].iter().all(|byte| byte & 0x80 == 0)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_audio;
+    use crate::{audio::Type as AudioType, key_stream::KeyStream};
+    use alloc::vec;
+
+    #[test]
+    fn test_find_audio() {
+        let key_stream = KeyStream::new(b"123456789");
+
+        let mut data = vec![0; 100];
+        // an MPEG frame sync alone is not trusted
+        data[10] = 0xFF;
+        data[11] = 0xFB;
+        key_stream.apply(0, &mut data[10..26]);
+        data[40..44].copy_from_slice(b"fLaC");
+        key_stream.apply(0, &mut data[40..56]);
+
+        assert_eq!(find_audio(&data, &key_stream, 0..100), Some((40, AudioType::Flac)));
+        assert_eq!(find_audio(&data, &key_stream, 0..40), None);
+        assert_eq!(find_audio(&data[..50], &key_stream, 0..100), None);
+
+        let mut data = vec![0; 100];
+        // "ID3" with a version or size no ID3 header has is not trusted either
+        data[10..20].copy_from_slice(b"ID3\x09\0\0\0\0\0\0");
+        key_stream.apply(0, &mut data[10..26]);
+        data[30..40].copy_from_slice(b"ID3\x04\0\0\0\x80\0\0");
+        key_stream.apply(0, &mut data[30..46]);
+        data[50..60].copy_from_slice(b"ID3\x04\0\0\0\0\x21\x7F");
+        key_stream.apply(0, &mut data[50..66]);
+
+        assert_eq!(find_audio(&data, &key_stream, 0..100), Some((50, AudioType::Mp3)));
+    }
+}
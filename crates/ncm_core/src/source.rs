@@ -0,0 +1,41 @@
+use crate::error::{Error, Result};
+
+/// A minimal byte reader, which lets the decoder run without `std::io`.
+///
+/// With the `std` feature, [`crate::decoder::Decoder::decode`] takes any [`std::io::Read`] instead.
+pub trait ByteSource {
+    /// Reads up to `buf.len()` bytes, returning how many were read, `0` once the source is exhausted.
+    fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, SourceError>;
+}
+
+/// The error of a [`ByteSource`] which failed to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceError;
+
+impl ByteSource for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, SourceError> {
+        let size = self.len().min(buf.len());
+        let (head, tail) = self.split_at(size);
+        buf[..size].copy_from_slice(head);
+        *self = tail;
+
+        Ok(size)
+    }
+}
+
+/// Fills as much of `buf` as `source` has, returning how many bytes were read.
+pub(crate) fn read_up_to<S>(source: &mut S, buf: &mut [u8], offset: u64) -> Result<usize>
+where
+    S: ByteSource,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        match source.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(size) => len += size,
+            Err(SourceError) => return Err(Error::Source { offset }),
+        }
+    }
+
+    Ok(len)
+}
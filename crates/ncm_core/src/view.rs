@@ -0,0 +1,143 @@
+use crate::{
+    audio::{Type as AudioType, SNIFF_LEN},
+    header::Header,
+    image::Type as ImageType,
+    key_stream::KeyStream,
+    layout::Layout,
+    meta::MetaKind,
+    parser::Truncation,
+    recovery::Recovery,
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// An ncm file decoded in place by [`crate::decoder::Decoder::from_slice`].
+///
+/// The image and the audio borrow the input, only the small key and comment frames,
+/// which have to be decrypted anyway, are copied.
+#[derive(Debug, Clone)]
+pub struct NcmView<'a> {
+    pub header: Header,
+    pub key: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
+    pub meta: Vec<u8>,
+    pub image: Option<&'a [u8]>,
+    /// The still encrypted audio data, see [`NcmView::decrypt_audio_into`].
+    pub audio: &'a [u8],
+    /// Set if the input ended before the audio, only with [`crate::options::DecodeOptions::lenient`].
+    pub truncated: Option<Truncation>,
+    /// Set if the audio was found by searching, only with [`crate::options::DecodeOptions::recover`].
+    pub recovered: Option<Recovery>,
+    pub layout: Layout,
+    pub(crate) audio_offset: u64,
+    pub(crate) key_stream: KeyStream,
+}
+
+impl NcmView<'_> {
+    pub fn audio_type(&self) -> AudioType {
+        let mut buf = [0; SNIFF_LEN];
+        let len = self.audio.len().min(SNIFF_LEN);
+        buf[..len].copy_from_slice(&self.audio[..len]);
+        self.key_stream.apply(0, &mut buf[..len]);
+
+        AudioType::sniff(&buf[..len])
+    }
+
+    pub fn ext(&self) -> String {
+        self.audio_type().to_string()
+    }
+
+    pub fn image_type(&self) -> Option<ImageType> {
+        self.image.map(ImageType::sniff)
+    }
+
+    /// The offset of the audio data from the start of the ncm file.
+    pub fn audio_offset(&self) -> u64 {
+        self.audio_offset
+    }
+
+    /// Decrypts the whole audio into `output`, e.g. a memory map of the output file.
+    ///
+    /// # Panics
+    ///
+    /// If `output` is not exactly as long as [`NcmView::audio`].
+    pub fn decrypt_audio_into(&self, output: &mut [u8]) {
+        output.copy_from_slice(self.audio);
+
+        #[cfg(feature = "rayon")]
+        self.key_stream.apply_parallel(0, output);
+        #[cfg(not(feature = "rayon"))]
+        self.key_stream.apply(0, output);
+    }
+
+    /// Decrypts the audio chunk by chunk into `output`.
+    #[cfg(feature = "std")]
+    pub fn write_audio<W>(&self, mut output: W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        let mut buffer = [0; 0x8000];
+        let mut position = 0;
+        for chunk in self.audio.chunks(buffer.len()) {
+            let buf = &mut buffer[..chunk.len()];
+            buf.copy_from_slice(chunk);
+            self.key_stream.apply(position, buf);
+            output.write_all(buf)?;
+            position += chunk.len() as u64;
+        }
+
+        output.flush()
+    }
+}
+
+impl<'a> NcmView<'a> {
+    /// A reader of the decrypted audio, decrypting as it goes.
+    #[cfg(feature = "std")]
+    pub fn audio_reader(&self) -> AudioReader<'a> {
+        AudioReader { audio: self.audio, position: 0, key_stream: self.key_stream.clone() }
+    }
+}
+
+/// Reads the decrypted audio of an [`NcmView`], see [`NcmView::audio_reader`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct AudioReader<'a> {
+    audio: &'a [u8],
+    position: usize,
+    key_stream: KeyStream,
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for AudioReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = &self.audio[self.position..];
+        let size = data.len().min(buf.len());
+        let buf = &mut buf[..size];
+        buf.copy_from_slice(&data[..size]);
+
+        self.key_stream.apply(self.position as u64, buf);
+
+        self.position += size;
+        Ok(size)
+    }
+}
+
+#[cfg(feature = "std")]
+impl crate::format::AudioStream for AudioReader<'_> {
+    fn decrypt_into(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        let start = buf.len();
+        buf.extend_from_slice(&self.audio[self.position..]);
+
+        let chunk = &mut buf[start..];
+        #[cfg(feature = "rayon")]
+        self.key_stream.apply_parallel(self.position as u64, chunk);
+        #[cfg(not(feature = "rayon"))]
+        self.key_stream.apply(self.position as u64, chunk);
+
+        self.position = self.audio.len();
+        Ok(chunk.len())
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::Fixture;
+use ncm_core::{
+    async_decoder::AsyncDecoder, decoder::Decoder, encoder::Encoder, key::KeySet,
+    options::DecodeOptions, qmc,
+};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_async(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    block_on(async {
+        let decoder = AsyncDecoder::decode(&*fixture.data).await.unwrap();
+        assert_eq!(decoder.layout, Decoder::decode(&*fixture.data).unwrap().layout);
+        assert_eq!(decoder.key, fixture.key);
+        assert_eq!(decoder.comment, fixture.comment);
+        assert_eq!(decoder.meta, fixture.meta);
+        assert_eq!(
+            decoder.image.map(|image| image.into_data()),
+            fixture.image.map(|image| image.into_data())
+        );
+
+        let mut audio = decoder.audio;
+        assert_eq!(audio.r#type(), fixture.audio_type);
+        let mut decoded = vec![];
+        audio.read_to_end(&mut decoded).await.unwrap();
+        assert_eq!(decoded, fixture.audio);
+
+        let mut data = fixture.audio.clone();
+        qmc::apply(0, &mut data);
+        let mut audio = qmc::decode_async(&*data).await.unwrap();
+        assert_eq!(audio.r#type(), fixture.audio_type);
+        let mut decoded = vec![];
+        audio.read_to_end(&mut decoded).await.unwrap();
+        assert_eq!(decoded, fixture.audio);
+    });
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_key_set(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    let keys = KeySet {
+        core_key: *b"0123456789abcdef",
+        meta_key: *b"fedcba9876543210",
+        key_xor: 0x5A,
+        comment_xor: 0xA5,
+    };
+    let encoder = Encoder {
+        key: &fixture.key,
+        meta_kind: fixture.meta_kind,
+        meta: &fixture.meta,
+        image: fixture.image_data(),
+        keys,
+    };
+    let mut packed = vec![];
+    encoder.encode(&*fixture.audio, &mut packed).unwrap();
+
+    let options = DecodeOptions { keys, ..Default::default() };
+    block_on(async {
+        let decoder = AsyncDecoder::decode_with(&*packed, options.clone()).await.unwrap();
+        assert_eq!(decoder.key, fixture.key);
+        assert_eq!(decoder.meta, fixture.meta);
+        let mut decoded = vec![];
+        let mut audio = decoder.audio;
+        audio.read_to_end(&mut decoded).await.unwrap();
+        assert_eq!(decoded, fixture.audio);
+
+        let lenient = DecodeOptions { lenient: true, ..options };
+        let decoder = AsyncDecoder::decode_with(&packed[..200], lenient).await.unwrap();
+        assert!(decoder.truncated.is_some());
+    });
+}
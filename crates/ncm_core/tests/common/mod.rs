@@ -0,0 +1,54 @@
+use ncm_core::{
+    audio::Type as AudioType, decoder::Decoder, header::Header, image::Image, meta::MetaKind,
+};
+use std::{fs, io::Read, path::Path};
+
+/// An ncm fixture decoded with [`Decoder::decode`], the result every other way of decoding it is
+/// checked against.
+#[allow(dead_code)]
+pub struct Fixture {
+    /// The whole ncm file.
+    pub data: Vec<u8>,
+    pub header: Header,
+    pub key: Vec<u8>,
+    pub comment: Vec<u8>,
+    pub meta_kind: MetaKind,
+    pub meta: Vec<u8>,
+    pub image: Option<Image>,
+    pub audio_type: AudioType,
+    pub audio_offset: u64,
+    /// The decrypted audio.
+    pub audio: Vec<u8>,
+}
+
+impl Fixture {
+    pub fn load(input: &Path) -> Self {
+        let data = fs::read(input).unwrap();
+
+        let Decoder { header, key, comment, meta_kind, meta, image, mut audio, .. } =
+            Decoder::decode(&*data).unwrap();
+        let audio_type = audio.r#type();
+        let audio_offset = audio.offset();
+        let mut audio_data = vec![];
+        audio.read_to_end(&mut audio_data).unwrap();
+
+        Self {
+            data,
+            header,
+            key,
+            comment,
+            meta_kind,
+            meta,
+            image,
+            audio_type,
+            audio_offset,
+            audio: audio_data,
+        }
+    }
+
+    /// The image data, borrowed the way [`ncm_core::encoder::Encoder`] takes it.
+    #[allow(dead_code)]
+    pub fn image_data(&self) -> Option<&[u8]> {
+        self.image.as_ref().map(|image| &**image.data())
+    }
+}
@@ -0,0 +1,607 @@
+mod common;
+
+use common::Fixture;
+use ncm_core::{
+    audio::Type as AudioType,
+    decoder::{Decoder, Probe},
+    encoder::Encoder,
+    format::{Confidence, EncryptedFormat, Ncm, Registry},
+    input::Input,
+    key::KeySet,
+    key_stream::KeyStream,
+    layout::Span,
+    meta::Meta,
+    meta_key::{decode_163_key, decode_163_key_with, encode_163_key},
+    options::DecodeOptions,
+    parser::{Event, NcmParser, Truncation},
+    qmc::{self, Qmc},
+    recovery::Recovery,
+    Error, Frame,
+};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_dump(input: PathBuf) {
+    let Fixture { key, comment, meta, image, .. } = Fixture::load(&input);
+
+    let expected_key = fs::read(input.with_extension("key")).unwrap();
+    assert_eq!(key, expected_key);
+
+    if !comment.is_empty() {
+        let expected_comment = fs::read(input.with_extension("comment")).unwrap();
+        assert_eq!(comment, expected_comment);
+    }
+
+    if !meta.is_empty() {
+        let expected_meta = fs::read(input.with_extension("json")).unwrap();
+        assert_eq!(meta, expected_meta);
+    }
+
+    if let Some(image) = image {
+        let expected_image = fs::read(input.with_extension(image.ext())).unwrap();
+        assert_eq!(image.data(), &expected_image);
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_pack(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    let encoder = Encoder {
+        key: &fixture.key,
+        meta_kind: fixture.meta_kind,
+        meta: &fixture.meta,
+        image: fixture.image_data(),
+        keys: KeySet::default(),
+    };
+    assert_eq!(encoder.comment(), fixture.comment);
+
+    if !fixture.comment.is_empty() {
+        let decoded = decode_163_key(std::str::from_utf8(&fixture.comment).unwrap()).unwrap();
+        assert_eq!(decoded, Meta { kind: fixture.meta_kind, json: fixture.meta.clone() });
+        assert_eq!(encode_163_key(&decoded).as_bytes(), fixture.comment);
+    }
+
+    let mut packed = vec![];
+    encoder.encode(&*fixture.audio, &mut packed).unwrap();
+
+    let options = DecodeOptions { verify_crc: true, ..Default::default() };
+    let Decoder { key, comment, meta_kind, meta, image, mut audio, .. } =
+        Decoder::decode_with(&*packed, options).unwrap();
+    assert_eq!(key, fixture.key);
+    assert_eq!(comment, fixture.comment);
+    assert_eq!(meta_kind, fixture.meta_kind);
+    assert_eq!(meta, fixture.meta);
+    assert_eq!(image.map(|image| image.into_data()), fixture.image.map(|image| image.into_data()));
+
+    let mut packed_audio = vec![];
+    audio.read_to_end(&mut packed_audio).unwrap();
+    assert_eq!(packed_audio, fixture.audio);
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_seek(input: PathBuf) {
+    let Fixture { data, audio: audio_data, .. } = Fixture::load(&input);
+
+    let Decoder { mut audio, .. } = Decoder::decode(io::Cursor::new(&data)).unwrap();
+    assert_eq!(audio.offset() + audio_data.len() as u64, data.len() as u64);
+
+    let mut buf = [0; 1000];
+    for pos in [0, 5, 12, 255, 256, 1000, audio_data.len() as u64 - 1000] {
+        assert_eq!(audio.seek(SeekFrom::Start(pos)).unwrap(), pos);
+        audio.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, audio_data[pos as usize..][..1000]);
+    }
+
+    let len = audio_data.len();
+    assert_eq!(audio.seek(SeekFrom::Current(-1995)).unwrap(), len as u64 - 1995);
+    audio.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, audio_data[len - 1995..][..1000]);
+
+    assert_eq!(audio.seek(SeekFrom::End(-3)).unwrap(), len as u64 - 3);
+    let mut tail = vec![];
+    audio.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, audio_data[len - 3..]);
+
+    assert!(audio.seek(SeekFrom::Current(-(len as i64) - 1)).is_err());
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_error(input: PathBuf) {
+    let data = fs::read(&input).unwrap();
+
+    let err = Decoder::decode(&data[..5]).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedEof { offset: 0 }), "{err}");
+
+    let err = Decoder::decode(&data[..200]).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedEof { offset } if offset > 10), "{err}");
+
+    let err = Decoder::decode(&data[1..]).unwrap_err();
+    assert!(matches!(err, Error::HeaderMismatch { offset: 0 }), "{err}");
+
+    let mut corrupt = data.clone();
+    corrupt[14] ^= 0xFF;
+    let err = Decoder::decode(&*corrupt).unwrap_err();
+    assert!(
+        matches!(err, Error::Padding { offset: 10 } | Error::InvalidKey { offset: 10 }),
+        "{err}"
+    );
+
+    let mut corrupt = data.clone();
+    corrupt[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = Decoder::decode(&*corrupt).unwrap_err();
+    assert!(
+        matches!(err, Error::FrameTooLarge { offset: 10, frame: Frame::Key, len, .. } if len == u32::MAX as u64),
+        "{err}"
+    );
+
+    let probe = Decoder::probe(io::Cursor::new(&data)).unwrap();
+    if probe.image_len > 0 {
+        let options = DecodeOptions { max_image_len: probe.image_len - 1, ..Default::default() };
+        let err = Decoder::decode_with(&*data, options).unwrap_err();
+        assert!(
+            matches!(err, Error::FrameTooLarge { offset, frame: Frame::Image, .. } if offset == probe.image_offset - 4),
+            "{err}"
+        );
+    }
+
+    for len in 0..probe.image_offset as usize + 16 {
+        let err = Decoder::decode(&data[..len]).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEof { .. }), "{len}: {err}");
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_input(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    assert_eq!(Input::sniff(&fixture.data), Input::Ncm);
+    assert_eq!(Input::sniff(&fixture.audio), Input::Audio(fixture.audio_type));
+
+    let err = Decoder::decode(&*fixture.audio).unwrap_err();
+    assert!(matches!(err, Error::HeaderMismatch { .. }), "{err}");
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_parser(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    for chunk_size in [1, 7, 4096] {
+        let mut parser = NcmParser::new();
+        let mut events = vec![];
+        for chunk in fixture.data.chunks(chunk_size) {
+            events.extend(parser.feed(chunk).unwrap());
+        }
+        parser.finish().unwrap();
+
+        let mut parsed_audio = vec![];
+        for event in events {
+            match event {
+                Event::Key(data) => assert_eq!(data, fixture.key),
+                Event::Comment(data) => assert_eq!(data, fixture.comment),
+                Event::Meta(kind, data) => {
+                    assert_eq!((kind, data), (fixture.meta_kind, fixture.meta.clone()))
+                }
+                Event::Header(data) => assert_eq!(data, fixture.header),
+                Event::Image(data) => {
+                    assert_eq!(Some(data.data()), fixture.image.as_ref().map(|image| image.data()))
+                }
+                Event::AudioChunk(data) => parsed_audio.extend(data),
+            }
+        }
+        assert_eq!(parsed_audio, fixture.audio);
+    }
+
+    let mut parser = NcmParser::new();
+    parser.feed(&fixture.data[..1000]).unwrap();
+    assert!(matches!(parser.finish(), Err(Error::UnexpectedEof { .. })));
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_decode_source(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let data = &*fixture.data;
+
+    let decoder = Decoder::decode_source(data).unwrap();
+    assert_eq!(decoder.key, fixture.key);
+    assert_eq!(decoder.comment, fixture.comment);
+    assert_eq!(decoder.meta, fixture.meta);
+    assert_eq!(decoder.audio_offset(), fixture.audio_offset);
+    assert_eq!(
+        decoder.image.map(|image| image.into_data()),
+        fixture.image.map(|image| image.into_data())
+    );
+
+    let mut audio = decoder.audio;
+    let mut source_audio = vec![];
+    let mut buf = [0; 1000];
+    loop {
+        let size = audio.read_source(&mut buf).unwrap();
+        if size == 0 {
+            break;
+        }
+        source_audio.extend_from_slice(&buf[..size]);
+    }
+    assert_eq!(source_audio, fixture.audio);
+
+    let err = Decoder::decode_source(&data[..200]).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedEof { offset } if offset > 10), "{err}");
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_probe(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let image_len = fixture.image.as_ref().map_or(0, |image| image.data().len());
+
+    let probe = Decoder::probe(io::Cursor::new(&fixture.data)).unwrap();
+    assert_eq!(probe.key, fixture.key);
+    assert_eq!(probe.comment, fixture.comment);
+    assert_eq!(probe.meta, fixture.meta);
+    assert_eq!(probe.image_len, image_len as u64);
+    assert_eq!(probe.audio_offset, fixture.audio_offset);
+
+    let image_offset = probe.image_offset as usize;
+    assert_eq!(
+        fixture.image.as_ref().map(|image| &**image.data()),
+        (image_len > 0).then(|| &fixture.data[image_offset..image_offset + image_len])
+    );
+
+    let options = DecodeOptions { load_image: false, ..Default::default() };
+    let mut skipped = Decoder::decode_with(&*fixture.data, options).unwrap();
+    assert!(skipped.image.is_none());
+    assert_eq!(skipped.audio_offset(), fixture.audio_offset);
+    let mut audio = vec![];
+    skipped.audio.read_to_end(&mut audio).unwrap();
+    assert_eq!(audio, fixture.audio);
+
+    // a file cut off in the image is not skipped over as if it were complete
+    let lenient = DecodeOptions { lenient: true, ..Default::default() };
+    let audio_offset = fixture.audio_offset as usize;
+    let cuts = [image_offset + image_len / 2, audio_offset, audio_offset + 8];
+    for cut in cuts.into_iter().filter(|&cut| cut > image_offset) {
+        let err = Decoder::probe(io::Cursor::new(&fixture.data[..cut])).unwrap_err();
+        let decode_err = Decoder::decode(&fixture.data[..cut]).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEof { .. }), "{cut}: {err}");
+        assert_eq!(err.to_string(), decode_err.to_string());
+
+        let probe =
+            Decoder::probe_with(io::Cursor::new(&fixture.data[..cut]), lenient.clone()).unwrap();
+        let decoder = Decoder::decode_with(&fixture.data[..cut], lenient.clone()).unwrap();
+        assert_eq!(probe.truncated, decoder.truncated);
+        assert_eq!(probe.audio_offset, decoder.audio_offset());
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_crc(input: PathBuf) {
+    let mut data = fs::read(&input).unwrap();
+    let options = DecodeOptions { verify_crc: true, ..Default::default() };
+
+    let decoder = Decoder::decode_with(&*data, options.clone()).unwrap();
+    assert_eq!(&decoder.header.version, &data[8..10]);
+    assert_eq!(decoder.header.gap, 0x01);
+
+    let crc = decoder.layout.crc;
+    let crc_offset = crc.offset as usize;
+    assert_eq!(decoder.header.crc.to_le_bytes(), data[crc_offset..crc.end() as usize]);
+
+    data[crc_offset] ^= 0xFF;
+    Decoder::decode(&*data).unwrap();
+    let err = Decoder::decode_with(&*data, options).unwrap_err();
+    assert!(matches!(err, Error::CrcMismatch { offset, .. } if offset == crc.offset), "{err}");
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_key_stream(input: PathBuf) {
+    let Fixture { data, key, audio_offset, audio, .. } = Fixture::load(&input);
+    let offset = audio_offset as usize;
+
+    let key_stream = KeyStream::new(&key);
+    let ranges = [(0, 12), (1000, 5000), (4095, 70000), (audio.len() - 300, audio.len())];
+    let handles = ranges.map(|(start, end)| {
+        let key_stream = key_stream.clone();
+        let mut chunk = data[offset + start..offset + end].to_vec();
+        std::thread::spawn(move || {
+            key_stream.apply(start as u64, &mut chunk);
+            chunk
+        })
+    });
+
+    for ((start, end), handle) in ranges.into_iter().zip(handles) {
+        assert_eq!(handle.join().unwrap(), audio[start..end]);
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_view(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let data = &*fixture.data;
+
+    let view = Decoder::from_slice(data).unwrap();
+    assert_eq!(view.header, fixture.header);
+    assert_eq!(view.key, fixture.key);
+    assert_eq!(view.comment, fixture.comment);
+    assert_eq!(view.meta_kind, fixture.meta_kind);
+    assert_eq!(view.meta, fixture.meta);
+    assert_eq!(view.image, fixture.image_data());
+    assert_eq!(view.image_type(), fixture.image.as_ref().map(|image| image.r#type()));
+    assert_eq!(view.audio_offset(), fixture.audio_offset);
+    assert_eq!(view.audio_type(), fixture.audio_type);
+
+    let mut view_audio = vec![0; view.audio.len()];
+    view.decrypt_audio_into(&mut view_audio);
+    assert_eq!(view_audio, fixture.audio);
+
+    let mut view_audio = vec![];
+    view.write_audio(&mut view_audio).unwrap();
+    assert_eq!(view_audio, fixture.audio);
+
+    let mut view_audio = vec![];
+    view.audio_reader().read_to_end(&mut view_audio).unwrap();
+    assert_eq!(view_audio, fixture.audio);
+
+    #[cfg(feature = "mmap")]
+    {
+        let file = fs::File::open(&input).unwrap();
+        // SAFETY: the fixtures are not written to while the tests run
+        let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+        assert_eq!(Decoder::from_mmap(&mmap).unwrap().audio, view.audio);
+    }
+
+    let audio_offset = fixture.audio_offset as usize;
+    for len in (0..1000).chain(audio_offset - 16..audio_offset + 12) {
+        let err = Decoder::from_slice(&data[..len]).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEof { .. }), "{len}: {err}");
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_truncated(input: PathBuf) {
+    let data = fs::read(&input).unwrap();
+    let options = DecodeOptions { lenient: true, ..Default::default() };
+
+    let Probe { meta, image_offset, image_len, audio_offset, .. } =
+        Decoder::probe(io::Cursor::new(&data)).unwrap();
+    let audio_len = data.len() as u64 - audio_offset;
+
+    if image_len > 0 {
+        let cut = (image_offset + image_len / 2) as usize;
+
+        let err = Decoder::decode(&data[..cut]).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEof { .. }), "{err}");
+
+        let truncation = Truncation { offset: cut as u64, missing: audio_offset - cut as u64 };
+
+        let mut decoder = Decoder::decode_with(&data[..cut], options.clone()).unwrap();
+        assert_eq!(decoder.meta, meta);
+        assert_eq!(decoder.image.unwrap().data(), &data[image_offset as usize..cut]);
+        assert_eq!(decoder.truncated, Some(truncation));
+        assert_eq!(decoder.audio.read(&mut [0; 16]).unwrap(), 0);
+
+        let view = Decoder::from_slice_with(&data[..cut], options.clone()).unwrap();
+        assert_eq!(view.meta, meta);
+        assert_eq!(view.image, Some(&data[image_offset as usize..cut]));
+        assert_eq!(view.truncated, Some(truncation));
+        assert!(view.audio.is_empty());
+    }
+
+    // in the middle of the comment frame, the key survives but the meta does not
+    let decoder = Decoder::decode_with(&data[..200], options.clone()).unwrap();
+    assert!(!decoder.key.is_empty());
+    assert!(decoder.meta.is_empty());
+    assert!(matches!(decoder.truncated, Some(Truncation { offset: 200, missing }) if missing > 0));
+
+    // without a complete key nothing can be decrypted
+    let err = Decoder::decode_with(&data[..50], options.clone()).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedEof { offset: 10 }), "{err}");
+
+    // the audio length is not declared, a short audio is only noticed by the player
+    let cut = (audio_offset + audio_len / 2) as usize;
+    let mut decoder = Decoder::decode_with(&data[..cut], options).unwrap();
+    assert_eq!(decoder.truncated, None);
+    let mut audio = vec![];
+    decoder.audio.read_to_end(&mut audio).unwrap();
+    assert_eq!(audio.len() as u64, audio_len / 2);
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_recover(input: PathBuf) {
+    let data = fs::read(&input).unwrap();
+    let options = DecodeOptions { recover: true, ..Default::default() };
+
+    let Probe { image_offset, audio_offset, .. } = Decoder::probe(io::Cursor::new(&data)).unwrap();
+    let image_offset = image_offset as usize;
+    let expected = Decoder::from_slice(&data).unwrap();
+    assert_eq!(expected.recovered, None);
+
+    let view = Decoder::from_slice_with(&data, options.clone()).unwrap();
+    assert_eq!(view.recovered, None);
+
+    // a cover frame 50 bytes too long swallows the start of the audio
+    let mut corrupt = data.clone();
+    let cover_len =
+        u32::from_le_bytes(corrupt[image_offset - 8..image_offset - 4].try_into().unwrap());
+    corrupt[image_offset - 8..image_offset - 4].copy_from_slice(&(cover_len + 50).to_le_bytes());
+
+    let view = Decoder::from_slice(&corrupt).unwrap();
+    assert_eq!(view.audio_offset(), audio_offset + 50);
+    assert_eq!(view.audio_type(), AudioType::Unknown);
+
+    let view = Decoder::from_slice_with(&corrupt, options.clone()).unwrap();
+    assert_eq!(view.recovered, Some(Recovery { declared_offset: audio_offset + 50, audio_offset }));
+    assert_eq!(view.audio_type(), expected.audio_type());
+    assert_eq!(view.audio, expected.audio);
+
+    // an image length far past the end of the file
+    let mut corrupt = data.clone();
+    corrupt[image_offset - 4..image_offset].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    let err = Decoder::from_slice(&corrupt).unwrap_err();
+    assert!(matches!(err, Error::FrameTooLarge { frame: Frame::Image, .. }), "{err}");
+
+    let view = Decoder::from_slice_with(&corrupt, options).unwrap();
+    assert_eq!(view.recovered.unwrap().audio_offset, audio_offset);
+    assert_eq!(view.image, expected.image);
+    assert_eq!(view.audio, expected.audio);
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_layout(input: PathBuf) {
+    let data = fs::read(&input).unwrap();
+
+    let view = Decoder::from_slice(&data).unwrap();
+    let layout = view.layout;
+    assert_eq!(layout.magic, Span { offset: 0, len: 8 });
+    assert_eq!(layout.version, Span { offset: 8, len: 2 });
+    assert_eq!(layout.key.offset, layout.version.end());
+    assert_eq!(layout.comment.offset, layout.key.end());
+    assert_eq!(layout.crc, Span { offset: layout.comment.end(), len: 4 });
+    assert_eq!(layout.gap, Span { offset: layout.crc.end(), len: 1 });
+    assert_eq!(layout.cover.offset, layout.gap.end());
+    assert_eq!(layout.image.offset, layout.cover.offset + 8);
+    assert!(layout.image.end() <= layout.cover.end());
+    assert_eq!(layout.audio, Span { offset: layout.cover.end(), len: view.audio.len() as u64 });
+    assert_eq!(layout.audio.end(), data.len() as u64);
+
+    let probe = Decoder::probe(io::Cursor::new(&data)).unwrap();
+    assert_eq!(probe.layout, layout);
+    assert_eq!(probe.image_offset, layout.image.offset);
+    assert_eq!(probe.image_len, layout.image.len);
+    assert_eq!(probe.audio_offset, layout.audio.offset);
+
+    // a reader does not know where the input ends
+    let decoder = Decoder::decode(&*data).unwrap();
+    assert_eq!(decoder.layout.audio.len, 0);
+    assert_eq!(decoder.layout.parts()[..8], layout.parts()[..8]);
+
+    // a truncated input leaves the parts it did not reach empty
+    let cut = layout.crc.offset as usize + 2;
+    let options = DecodeOptions { lenient: true, ..Default::default() };
+    let view = Decoder::from_slice_with(&data[..cut], options).unwrap();
+    assert_eq!(view.layout.comment, layout.comment);
+    assert_eq!(view.layout.crc, Span::default());
+    assert_eq!(view.layout.audio, Span::default());
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_key_set(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    let keys = KeySet {
+        core_key: *b"0123456789abcdef",
+        meta_key: *b"fedcba9876543210",
+        key_xor: 0x5A,
+        comment_xor: 0xA5,
+    };
+    let encoder = Encoder {
+        key: &fixture.key,
+        meta_kind: fixture.meta_kind,
+        meta: &fixture.meta,
+        image: fixture.image_data(),
+        keys,
+    };
+    let mut packed = vec![];
+    encoder.encode(&*fixture.audio, &mut packed).unwrap();
+
+    let err = Decoder::decode(&*packed).unwrap_err();
+    assert!(matches!(err, Error::Padding { .. } | Error::InvalidKey { .. }), "{err}");
+
+    let options = DecodeOptions { keys, ..Default::default() };
+    let mut decoder = Decoder::decode_with(&*packed, options.clone()).unwrap();
+    assert_eq!(decoder.key, fixture.key);
+    assert_eq!(decoder.comment, encoder.comment());
+    assert_eq!(decoder.meta, fixture.meta);
+    let mut decoded = vec![];
+    decoder.audio.read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, fixture.audio);
+
+    let view = Decoder::from_slice_with(&packed, options.clone()).unwrap();
+    assert_eq!(view.meta, fixture.meta);
+
+    let probe = Decoder::probe_with(io::Cursor::new(&packed), options.clone()).unwrap();
+    assert_eq!(probe.key, fixture.key);
+    assert_eq!(probe.meta, fixture.meta);
+
+    let decoder = Decoder::decode_source_with(&*packed, options).unwrap();
+    assert_eq!(decoder.key, fixture.key);
+    assert_eq!(decoder.meta, fixture.meta);
+
+    if !fixture.comment.is_empty() {
+        let comment = std::str::from_utf8(&fixture.comment).unwrap();
+        assert_eq!(
+            decode_163_key_with(comment, &KeySet::default()).unwrap(),
+            decode_163_key(comment).unwrap()
+        );
+        assert!(decode_163_key_with(comment, &keys).is_err());
+    }
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_registry(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let data = &*fixture.data;
+    assert_eq!(Ncm.sniff(data), Confidence::Certain);
+
+    let registry = Registry::default();
+    assert_eq!(registry.detect(data).unwrap().name(), "ncm");
+
+    let options = DecodeOptions::default();
+    let streams = [
+        registry.open_slice(data, &options).unwrap(),
+        registry.open(fs::File::open(&input).unwrap(), &options).unwrap(),
+        registry.open(data, &DecodeOptions { recover: true, ..Default::default() }).unwrap(),
+    ];
+    for mut stream in streams {
+        assert_eq!(stream.audio_type, fixture.audio_type);
+        assert_eq!(stream.key, fixture.key);
+        assert_eq!(stream.comment, fixture.comment);
+        assert_eq!(stream.meta, Some(Meta { kind: fixture.meta_kind, json: fixture.meta.clone() }));
+        assert_eq!(stream.cover.as_ref().map(|cover| &**cover.data()), fixture.image_data());
+        assert_eq!(stream.layout.unwrap().audio.offset, fixture.audio_offset);
+        let mut stream_audio = vec![];
+        stream.decrypt_into(&mut stream_audio).unwrap();
+        assert_eq!(stream_audio, fixture.audio);
+    }
+
+    let err = registry.open_slice(&fixture.audio, &options).err().unwrap();
+    assert!(matches!(err, Error::UnsupportedFormat { offset: 0 }), "{err}");
+}
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_qmc(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    let mut data = fixture.audio.clone();
+    qmc::apply(0, &mut data);
+    assert_eq!(qmc::sniff(&data), fixture.audio_type);
+    assert_eq!(Qmc.sniff(&data), Confidence::Maybe);
+    assert_eq!(Input::sniff(&data), Input::Unknown);
+
+    let mut audio = qmc::decode(&*data).unwrap();
+    assert_eq!(audio.r#type(), fixture.audio_type);
+    let mut decoded = vec![];
+    audio.read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, fixture.audio);
+
+    let registry = Registry::default();
+    assert_eq!(registry.detect(&data).unwrap().name(), "qmc");
+    assert_eq!(registry.detect(&fixture.data).unwrap().name(), "ncm");
+
+    let options = DecodeOptions::default();
+    for stream in [registry.open_slice(&data, &options), registry.open(&*data, &options)] {
+        let mut stream = stream.unwrap();
+        assert_eq!(stream.audio_type, fixture.audio_type);
+        assert!(stream.key.is_empty());
+        assert!(stream.meta.is_none());
+        assert!(stream.layout.is_none());
+        let mut decoded = vec![];
+        stream.decrypt_into(&mut decoded).unwrap();
+        assert_eq!(decoded, fixture.audio);
+    }
+
+    let err = qmc::decode(&data[..8]).unwrap_err();
+    assert!(matches!(err, Error::UnexpectedEof { offset: 0 }), "{err}");
+}
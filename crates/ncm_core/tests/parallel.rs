@@ -0,0 +1,22 @@
+mod common;
+
+use common::Fixture;
+use ncm_core::{decoder::Decoder, key_stream::decrypt_parallel};
+use std::{io::Read, path::PathBuf};
+
+#[testing::fixture("../ncmc/tests/input/*.ncm")]
+fn test_parallel(input: PathBuf) {
+    let Fixture { data, key, audio_offset, audio, .. } = Fixture::load(&input);
+
+    let mut raw = data[audio_offset as usize..].to_vec();
+    decrypt_parallel(&mut raw, &key);
+    assert_eq!(raw, audio);
+
+    for skip in [0, 5, 12, 3000] {
+        let Decoder { audio: mut reader, .. } = Decoder::decode(&*data).unwrap();
+        let mut parallel = vec![0; skip];
+        reader.read_exact(&mut parallel).unwrap();
+        reader.read_to_end_parallel(&mut parallel).unwrap();
+        assert_eq!(parallel, audio);
+    }
+}
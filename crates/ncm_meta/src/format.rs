@@ -0,0 +1,75 @@
+use miniserde::json;
+use ncm_core::{audio::Type as AudioType, meta::MetaKind};
+
+use crate::music_meta::{DjMeta, MusicMeta};
+
+/// The audio type sniffed from the payload, next to the `format` the meta declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub sniffed: AudioType,
+    /// [`AudioType::Unknown`] without a meta or for a format this crate does not know.
+    pub declared: AudioType,
+}
+
+impl Format {
+    /// Reads the declared format from `meta`, a meta which does not parse declares nothing.
+    pub fn new(sniffed: AudioType, meta_kind: MetaKind, meta: &[u8]) -> Self {
+        let meta = String::from_utf8_lossy(meta);
+        let format = match meta_kind {
+            MetaKind::Music => json::from_str::<MusicMeta>(&meta).map(|meta| meta.format),
+            MetaKind::Dj => json::from_str::<DjMeta>(&meta).map(|meta| meta.main_music.format),
+        };
+
+        Self {
+            sniffed,
+            declared: format.map_or(AudioType::Unknown, |format| AudioType::from_ext(&format)),
+        }
+    }
+
+    /// The sniffed type, or the declared one if the payload was not recognised.
+    pub fn resolved(&self) -> AudioType {
+        match self.sniffed {
+            AudioType::Unknown => self.declared,
+            sniffed => sniffed,
+        }
+    }
+
+    /// Whether the payload is recognised as something other than the meta declares,
+    /// e.g. an MP3 in a file whose meta says flac.
+    pub fn is_mismatch(&self) -> bool {
+        self.sniffed != AudioType::Unknown
+            && self.declared != AudioType::Unknown
+            && self.sniffed != self.declared
+    }
+
+    /// The extension of [`Format::resolved`].
+    pub fn ext(&self) -> String {
+        self.resolved().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let meta = br#"{"musicId":1,"musicName":"a","artist":[["b",2]],"album":"c","albumPic":"","format":"flac"}"#;
+
+        let format = Format::new(AudioType::Flac, MetaKind::Music, meta);
+        assert_eq!(format.declared, AudioType::Flac);
+        assert!(!format.is_mismatch());
+
+        let format = Format::new(AudioType::Mp3, MetaKind::Music, meta);
+        assert!(format.is_mismatch());
+        assert_eq!(format.ext(), "mp3");
+
+        let format = Format::new(AudioType::Unknown, MetaKind::Music, meta);
+        assert!(!format.is_mismatch());
+        assert_eq!(format.ext(), "flac");
+
+        let format = Format::new(AudioType::Unknown, MetaKind::Music, b"");
+        assert_eq!(format.declared, AudioType::Unknown);
+        assert_eq!(format.ext(), "audio");
+    }
+}
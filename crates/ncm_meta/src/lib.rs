@@ -1,21 +1,31 @@
+mod format;
 mod music_meta;
 
 use anyhow::{Context, Ok, Result};
 use id3::TagLike;
 use miniserde::json;
-use ncm_core::{audio::Type as AudioType, decoder::Decoder};
+use ncm_core::{
+    audio::Type as AudioType,
+    decoder::Decoder,
+    format::DecodedStream,
+    image::Image,
+    meta::{Meta, MetaKind},
+    view::NcmView,
+};
 use std::{
     io::{Cursor, Read, Write},
     vec,
 };
 
-use crate::music_meta::MusicMeta;
+pub use crate::format::Format;
+use crate::music_meta::{DjMeta, MusicMeta};
 
 const TOOL_INFO: &str = include_str!("tool_info");
 
 pub struct Encoder {
     pub data: Vec<u8>,
     pub meta: String,
+    pub format: Format,
 }
 
 impl Encoder {
@@ -26,17 +36,70 @@ impl Encoder {
         let mut buffer = vec![];
         let audio_type = decoder.audio_type();
 
-        let Decoder { comment, meta, image, mut audio, .. } = decoder;
+        let Decoder { comment, meta_kind, meta, image, mut audio, .. } = decoder;
 
+        #[cfg(feature = "rayon")]
+        audio.read_to_end_parallel(&mut buffer)?;
+        #[cfg(not(feature = "rayon"))]
         audio.read_to_end(&mut buffer)?;
 
+        Self::tag(buffer, audio_type, &comment, meta_kind, &meta, image)
+    }
+
+    /// [`Encoder::encode`] for a file decoded in place, the audio is decrypted straight into the output.
+    pub fn encode_view(view: &NcmView) -> Result<Self> {
+        let mut buffer = vec![0; view.audio.len()];
+        view.decrypt_audio_into(&mut buffer);
+
+        let image = view.image.map(|image| Image::from(image.to_vec()));
+
+        Self::tag(buffer, view.audio_type(), &view.comment, view.meta_kind, &view.meta, image)
+    }
+
+    /// [`Encoder::encode`] for any format opened through an [`ncm_core::format::Registry`].
+    pub fn encode_stream(mut stream: DecodedStream) -> Result<Self> {
+        let mut buffer = vec![];
+        stream.decrypt_into(&mut buffer)?;
+
+        let DecodedStream { audio_type, comment, meta, cover, .. } = stream;
+        let Meta { kind, json } = meta.unwrap_or_default();
+        Self::tag(buffer, audio_type, &comment, kind, &json, cover)
+    }
+
+    /// Tags audio that was never encrypted, e.g. with the comment and cover written by dump mode.
+    pub fn encode_plain(
+        data: Vec<u8>,
+        comment: &[u8],
+        meta_kind: MetaKind,
+        meta: &[u8],
+        image: Option<Image>,
+    ) -> Result<Self> {
+        let audio_type = AudioType::sniff(&data);
+
+        Self::tag(data, audio_type, comment, meta_kind, meta, image)
+    }
+
+    fn tag(
+        mut buffer: Vec<u8>,
+        audio_type: AudioType,
+        comment: &[u8],
+        meta_kind: MetaKind,
+        meta: &[u8],
+        image: Option<Image>,
+    ) -> Result<Self> {
         if meta.is_empty() {
-            return Ok(Self { data: buffer, meta: "meta not found".into() });
+            let format = Format { sniffed: audio_type, declared: AudioType::Unknown };
+            return Ok(Self { data: buffer, meta: "meta not found".into(), format });
         }
 
-        let meta = String::from_utf8_lossy(&meta);
-        let music_meta: MusicMeta =
-            json::from_str(&meta).with_context(|| format!("failed to unpack: {meta}"))?;
+        let meta = String::from_utf8_lossy(meta);
+        let music_meta = match meta_kind {
+            MetaKind::Music => json::from_str::<MusicMeta>(&meta),
+            MetaKind::Dj => json::from_str::<DjMeta>(&meta).map(|dj_meta| dj_meta.main_music),
+        }
+        .with_context(|| format!("failed to unpack: {meta}"))?;
+        let format =
+            Format { sniffed: audio_type, declared: AudioType::from_ext(&music_meta.format) };
 
         match audio_type {
             AudioType::Flac => {
@@ -49,7 +112,7 @@ impl Encoder {
                 vorbis_comment
                     .set_artist(music_meta.artist.into_iter().map(|ar| ar.0).collect::<Vec<_>>());
                 vorbis_comment
-                    .set("DESCRIPTION", vec![String::from_utf8_lossy(&comment), TOOL_INFO.into()]);
+                    .set("DESCRIPTION", vec![String::from_utf8_lossy(comment), TOOL_INFO.into()]);
                 vorbis_comment.set("TOOL", vec![TOOL_INFO]);
 
                 if let Some(image) = image {
@@ -77,7 +140,7 @@ impl Encoder {
                 tag.add_frame(id3::frame::Comment {
                     lang: "eng".into(),
                     description: "".into(),
-                    text: String::from_utf8_lossy(&comment).into(),
+                    text: String::from_utf8_lossy(comment).into(),
                 });
                 tag.set_text("TSSE", TOOL_INFO);
                 tag.set_text("TENC", TOOL_INFO);
@@ -98,6 +161,6 @@ impl Encoder {
             _ => {}
         }
 
-        Ok(Self { data: buffer, meta: meta.into() })
+        Ok(Self { data: buffer, meta: meta.into(), format })
     }
 }
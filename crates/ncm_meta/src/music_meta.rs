@@ -14,6 +14,20 @@ pub(crate) struct MusicMeta {
     pub format: String,
 }
 
+/// The meta of a radio or DJ program, the track itself is in `mainMusic`.
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub(crate) struct DjMeta {
+    #[serde(rename = "programName")]
+    pub program_name: Option<String>,
+    #[serde(rename = "djName")]
+    pub dj_name: Option<String>,
+    #[serde(rename = "radioName")]
+    pub radio_name: Option<String>,
+    #[serde(rename = "mainMusic")]
+    pub main_music: MusicMeta,
+}
+
 make_place!(Place);
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -67,4 +81,14 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn test_deserialize_dj() {
+        let meta = r#"{"programId":2497484815,"programName":"Episode 1","mainMusic":{"musicId":2049527375,"musicName":"Episode 1","artist":[["Host",0]],"albumId":0,"album":"Radio","albumPic":"","bitrate":128000,"duration":1800000,"format":"mp3"},"djId":1,"djName":"Host","radioId":1,"radioName":"Radio","createTime":1685000000000}"#;
+        let dj_meta = json::from_str::<DjMeta>(meta).unwrap();
+        assert_eq!(dj_meta.program_name.as_deref(), Some("Episode 1"));
+        assert_eq!(dj_meta.radio_name.as_deref(), Some("Radio"));
+        assert_eq!(dj_meta.main_music.music_name, "Episode 1");
+        assert_eq!(dj_meta.main_music.format, "mp3");
+    }
 }
@@ -1,20 +1,94 @@
 use anyhow::{Context, Result};
 use bpaf::Bpaf;
-use ncm_core::decoder::Decoder;
-use ncm_meta::Encoder;
+#[cfg(any(unix, windows))]
+use memmap2::Mmap;
+use ncm_core::{
+    audio::SNIFF_LEN,
+    encoder::Encoder as NcmEncoder,
+    format::{EncryptedFormat, Registry},
+    image::{Image, Type as ImageType},
+    input::Input,
+    key::KeySet,
+    layout::{Layout, Span},
+    meta::{Meta, MetaKind},
+    meta_key::decode_163_key,
+    options::DecodeOptions,
+    parser::Truncation,
+    recovery::Recovery,
+};
+use ncm_meta::{Encoder, Format};
 use std::{
-    fs, io,
+    fs,
+    io::{self, Read},
+    ops::Deref,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options, version)]
-struct Opts {
-    #[bpaf(external, fallback(Mode::Auto))]
-    mode: Mode,
+enum Opts {
+    /// pack an audio file into an ncm file
+    #[bpaf(command)]
+    Pack {
+        /// key file, as written by dump mode
+        #[bpaf(short, long, argument("KEY"))]
+        key: PathBuf,
+
+        /// meta json file, as written by dump mode
+        #[bpaf(short, long, argument("META"))]
+        meta: Option<PathBuf>,
+
+        /// the meta describes a radio or DJ program rather than a song
+        #[bpaf(long)]
+        dj: bool,
+
+        /// cover image file
+        #[bpaf(short, long, argument("IMAGE"))]
+        image: Option<PathBuf>,
+
+        /// output file, defaults to AUDIO with the ncm extension
+        #[bpaf(short, long, argument("OUTPUT"))]
+        output: Option<PathBuf>,
+
+        #[bpaf(positional("AUDIO"))]
+        audio: PathBuf,
+    },
+
+    /// print where every part of ncm files sits, without converting them
+    #[bpaf(command)]
+    Inspect {
+        /// keep what can be read from inputs which end early instead of failing
+        #[bpaf(long)]
+        lenient: bool,
+
+        #[bpaf(positional("INPUT"))]
+        input: Vec<PathBuf>,
+    },
+
+    Convert {
+        #[bpaf(external, fallback(Mode::Auto))]
+        mode: Mode,
+
+        /// check the CRC32 of every input and fail on a mismatch
+        #[bpaf(long)]
+        verify: bool,
+
+        /// keep what can be read from inputs which end early instead of failing
+        #[bpaf(long)]
+        lenient: bool,
+
+        /// search for the audio when the cover frame lengths are corrupt
+        #[bpaf(long)]
+        recover: bool,
 
-    #[bpaf(positional("INPUT"))]
-    input: Vec<PathBuf>,
+        /// what to do with inputs which are already plain audio: skip, copy or retag, defaults to skip
+        #[bpaf(long, argument("ACTION"), fallback(Plain::Skip))]
+        plain: Plain,
+
+        #[bpaf(positional("INPUT"))]
+        input: Vec<PathBuf>,
+    },
 }
 
 #[derive(Debug, Clone, Bpaf, Default)]
@@ -26,26 +100,79 @@ enum Mode {
     Dump,
 }
 
-fn main() -> Result<()> {
-    let opts = opts().run();
+#[derive(Debug, Clone, Copy)]
+enum Plain {
+    /// leave the input alone
+    Skip,
+    /// copy the input next to it with the extension of its audio
+    Copy,
+    /// copy the input, tagged with the comment, meta and cover written beside it by dump mode,
+    /// inputs which already have the extension of their audio are copied to NAME.tagged.EXT
+    Retag,
+}
+
+impl FromStr for Plain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Plain::Skip),
+            "copy" => Ok(Plain::Copy),
+            "retag" => Ok(Plain::Retag),
+            _ => Err(format!("expected skip, copy or retag, got {s}")),
+        }
+    }
+}
 
-    match opts.mode {
-        Mode::Auto => auto(&opts.input),
-        Mode::Dump => dump(&opts.input),
+fn main() -> Result<()> {
+    match opts().run() {
+        Opts::Pack { key, meta, dj, image, output, audio } => {
+            let meta_kind = if dj { MetaKind::Dj } else { MetaKind::Music };
+            pack(&key, meta.as_deref(), meta_kind, image.as_deref(), output.as_deref(), &audio)
+        }
+        Opts::Inspect { lenient, input } => {
+            inspect(&input, &DecodeOptions { lenient, ..Default::default() })
+        }
+        Opts::Convert { mode, verify, lenient, recover, plain, input } => {
+            let options =
+                DecodeOptions { verify_crc: verify, lenient, recover, ..Default::default() };
+            match mode {
+                Mode::Auto => auto(&input, &options, plain),
+                Mode::Dump => dump(&input, &options, plain),
+            }
+        }
     }
 }
 
-fn auto(input_list: &[PathBuf]) -> Result<()> {
+fn auto(input_list: &[PathBuf], options: &DecodeOptions, plain: Plain) -> Result<()> {
+    let registry = Registry::default();
     for path in input_list {
-        let reader = fs::File::open(path).with_context(|| format!("input {}", path.display()))?;
-        let decoder = Decoder::decode(reader)?;
-        let ext = decoder.ext();
-        let output = Path::new(&path).with_extension(ext);
+        if convert_plain(path, plain)? {
+            continue;
+        }
+        let data = Contents::read(path)?;
+        let Some(format) = detect(&registry, path, &data) else {
+            continue;
+        };
+        let stream = format
+            .open_slice(&data, options)
+            .with_context(|| format!("input {}", path.display()))?;
+        if let Some(truncation) = stream.truncated {
+            warn_truncated(path, truncation);
+        }
+        if let Some(recovery) = stream.recovered {
+            warn_recovered(path, recovery);
+        }
+        let Encoder { data, meta, format } = Encoder::encode_stream(stream)?;
+        if data.is_empty() {
+            warn_no_audio(path);
+            continue;
+        }
+        warn_format(path, format);
+        let output = Path::new(&path).with_extension(format.ext());
 
         println!("{}", output.display());
 
-        let Encoder { data, meta } = Encoder::encode(decoder)?;
-
         eprintln!("{meta}");
 
         fs::write(output, data)?;
@@ -54,13 +181,33 @@ fn auto(input_list: &[PathBuf]) -> Result<()> {
     anyhow::Ok(())
 }
 
-fn dump(input_list: &[PathBuf]) -> Result<()> {
+fn dump(input_list: &[PathBuf], options: &DecodeOptions, plain: Plain) -> Result<()> {
+    let registry = Registry::default();
     for path in input_list {
-        let reader = fs::File::open(path).with_context(|| format!("input {}", path.display()))?;
+        if convert_plain(path, plain)? {
+            continue;
+        }
+        let data = Contents::read(path)?;
+        let Some(format) = detect(&registry, path, &data) else {
+            continue;
+        };
         println!("{}", path.display());
 
-        let Decoder { key, comment, meta, image, mut audio } = Decoder::decode(reader)?;
+        let mut stream = format
+            .open_slice(&data, options)
+            .with_context(|| format!("input {}", path.display()))?;
+        if let Some(truncation) = stream.truncated {
+            warn_truncated(path, truncation);
+        }
+        if let Some(recovery) = stream.recovered {
+            warn_recovered(path, recovery);
+        }
+        match &stream.layout {
+            Some(layout) => eprint!("{}", format_layout(layout)),
+            None => eprintln!("layout unsupported for {}", format.name()),
+        }
 
+        let Meta { kind: meta_kind, json: meta } = stream.meta.take().unwrap_or_default();
         {
             let meta = if !meta.is_empty() {
                 String::from_utf8_lossy(&meta)
@@ -70,30 +217,239 @@ fn dump(input_list: &[PathBuf]) -> Result<()> {
             eprintln!("{meta}");
         }
 
-        let key_path = path.with_extension("key");
-        fs::write(key_path, key)?;
+        if !stream.key.is_empty() {
+            let key_path = path.with_extension("key");
+            fs::write(key_path, &stream.key)?;
+        }
 
-        if !comment.is_empty() {
+        if !stream.comment.is_empty() {
             let comment_path = path.with_extension("comment");
-            fs::write(comment_path, comment)?;
+            fs::write(comment_path, &stream.comment)?;
         }
 
         if !meta.is_empty() {
             let meta_path = path.with_extension("json");
-            fs::write(meta_path, meta)?;
+            fs::write(meta_path, &meta)?;
         }
 
-        if let Some(image) = image {
+        if let Some(image) = &stream.cover {
             let image_path = path.with_extension(image.ext());
             fs::write(image_path, image.data())?;
         }
 
-        let audio_path = path.with_extension(audio.ext());
+        let mut audio = vec![];
+        stream.decrypt_into(&mut audio)?;
+        if audio.is_empty() {
+            warn_no_audio(path);
+            continue;
+        }
+
+        let format = Format::new(stream.audio_type, meta_kind, &meta);
+        warn_format(path, format);
+        let audio_path = path.with_extension(format.ext());
+
+        fs::write(audio_path, audio)?;
+    }
+
+    anyhow::Ok(())
+}
+
+fn inspect(input_list: &[PathBuf], options: &DecodeOptions) -> Result<()> {
+    let registry = Registry::default();
+    for path in input_list {
+        let data = Contents::read(path)?;
+        let Some(format) = detect(&registry, path, &data) else {
+            continue;
+        };
+        let stream = format
+            .open_slice(&data, options)
+            .with_context(|| format!("input {}", path.display()))?;
+        if let Some(truncation) = stream.truncated {
+            warn_truncated(path, truncation);
+        }
+
+        println!("{}", path.display());
+        match &stream.layout {
+            Some(layout) => print!("{}", format_layout(layout)),
+            None => println!("layout unsupported for {}", format.name()),
+        }
+    }
+
+    anyhow::Ok(())
+}
+
+/// The format of an input, see [`Registry::detect`], `None` with a warning if it has none,
+/// e.g. for the files dump mode writes beside the inputs.
+fn detect<'a>(registry: &'a Registry, path: &Path, data: &[u8]) -> Option<&'a dyn EncryptedFormat> {
+    let head = &data[..data.len().min(SNIFF_LEN)];
+    let format = registry.detect(head);
+    if format.is_none() {
+        eprintln!("skipping {}, it is not in a supported format", path.display());
+    }
+    format
+}
+
+/// The whole of an input, memory mapped where the platform supports it.
+enum Contents {
+    #[cfg(any(unix, windows))]
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Contents {
+    /// Maps `path`, falling back to reading it, e.g. on wasm32-wasi where mapping is unsupported.
+    fn read(path: &Path) -> Result<Self> {
+        let mut file = fs::File::open(path).with_context(|| format!("input {}", path.display()))?;
+
+        // SAFETY: the input is only read, and is not expected to change while it is converted
+        #[cfg(any(unix, windows))]
+        if let Ok(mmap) = unsafe { Mmap::map(&file) } {
+            return Ok(Contents::Mapped(mmap));
+        }
+
+        let mut data = vec![];
+        file.read_to_end(&mut data).with_context(|| format!("input {}", path.display()))?;
+        Ok(Contents::Read(data))
+    }
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(any(unix, windows))]
+            Contents::Mapped(mmap) => mmap,
+            Contents::Read(data) => data,
+        }
+    }
+}
+
+fn format_layout(layout: &Layout) -> String {
+    let mut table = format!("{:<8} {:>10} {:>10}\n", "part", "offset", "length");
+    for (name, Span { offset, len }) in layout.parts() {
+        table += &format!("{name:<8} {offset:>10} {len:>10}\n");
+    }
+    table
+}
+
+/// Handles an input which is already plain audio, returns `false` for anything else.
+fn convert_plain(path: &Path, plain: Plain) -> Result<bool> {
+    let mut head = vec![];
+    fs::File::open(path)
+        .with_context(|| format!("input {}", path.display()))?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    let Input::Audio(audio_type) = Input::sniff(&head) else {
+        return Ok(false);
+    };
+
+    let mut output = path.with_extension(audio_type.to_string());
+    match plain {
+        Plain::Copy if output != path => {
+            println!("{}", output.display());
+            fs::copy(path, output)?;
+            return Ok(true);
+        }
+        Plain::Skip | Plain::Copy => {
+            eprintln!("skipping {}, it is already {audio_type} audio", path.display());
+            return Ok(true);
+        }
+        // never rewrite the input, e.g. song.flac is tagged into song.tagged.flac
+        Plain::Retag if output == path => {
+            output = path.with_extension(format!("tagged.{audio_type}"))
+        }
+        Plain::Retag => {}
+    }
+
+    println!("{}", output.display());
+
+    let comment = fs::read(path.with_extension("comment")).unwrap_or_default();
+    let Meta { kind, json } = std::str::from_utf8(&comment)
+        .ok()
+        .and_then(|comment| decode_163_key(comment).ok())
+        .unwrap_or_else(|| Meta {
+            kind: MetaKind::Music,
+            json: fs::read(path.with_extension("json")).unwrap_or_default(),
+        });
+    let image = [ImageType::Jpeg, ImageType::Png, ImageType::Gif, ImageType::Bmp, ImageType::Webp]
+        .iter()
+        .find_map(|r#type| fs::read(path.with_extension(r#type.ext())).ok())
+        .map(Image::from);
+
+    let Encoder { data, meta, format } =
+        Encoder::encode_plain(fs::read(path)?, &comment, kind, &json, image)
+            .with_context(|| format!("input {}", path.display()))?;
+    warn_format(path, format);
+
+    eprintln!("{meta}");
+
+    fs::write(output, data)?;
+
+    Ok(true)
+}
+
+fn warn_format(path: &Path, format: Format) {
+    if format.is_mismatch() {
+        eprintln!(
+            "warning: {} holds {} audio, but its meta declares {}",
+            path.display(),
+            format.sniffed,
+            format.declared
+        );
+    }
+}
+
+fn warn_no_audio(path: &Path) {
+    eprintln!("warning: {} ends before the audio, no audio was written", path.display());
+}
+
+fn warn_recovered(path: &Path, Recovery { declared_offset, audio_offset }: Recovery) {
+    eprintln!(
+        "warning: {} has corrupt cover frame lengths, found the audio at offset {audio_offset} instead of {declared_offset}",
+        path.display()
+    );
+}
+
+fn warn_truncated(path: &Path, Truncation { offset, missing }: Truncation) {
+    eprintln!(
+        "warning: {} ends at offset {offset}, {missing} bytes short of the declared frames",
+        path.display()
+    );
+}
+
+fn pack(
+    key: &Path,
+    meta: Option<&Path>,
+    meta_kind: MetaKind,
+    image: Option<&Path>,
+    output: Option<&Path>,
+    audio: &Path,
+) -> Result<()> {
+    let key = fs::read(key).with_context(|| format!("key {}", key.display()))?;
+    let meta = match meta {
+        Some(path) => fs::read(path).with_context(|| format!("meta {}", path.display()))?,
+        None => vec![],
+    };
+    let image = match image {
+        Some(path) => Some(fs::read(path).with_context(|| format!("image {}", path.display()))?),
+        None => None,
+    };
+
+    let reader = fs::File::open(audio).with_context(|| format!("input {}", audio.display()))?;
+    let output = output.map_or_else(|| audio.with_extension("ncm"), Path::to_path_buf);
 
-        let mut file = fs::File::options().create(true).write(true).open(audio_path)?;
+    println!("{}", output.display());
 
-        io::copy(&mut audio, &mut file)?;
+    let writer = io::BufWriter::new(fs::File::create(output)?);
+    NcmEncoder {
+        key: &key,
+        meta_kind,
+        meta: &meta,
+        image: image.as_deref(),
+        keys: KeySet::default(),
     }
+    .encode(reader, writer)?;
 
     anyhow::Ok(())
 }
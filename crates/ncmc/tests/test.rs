@@ -1,36 +1,212 @@
-use ncm_core::decoder::Decoder;
-use std::{fs, path::PathBuf};
+#[path = "../../ncm_core/tests/common/mod.rs"]
+mod common;
+
+use common::Fixture;
+use ncm_core::{
+    audio::Type as AudioType, decoder::Decoder, encoder::Encoder, format::Registry, key::KeySet,
+    meta::MetaKind, options::DecodeOptions, qmc,
+};
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
 
 #[testing::fixture("tests/input/*.ncm")]
-fn test_dump(input: PathBuf) {
-    let reader = fs::File::open(&input).unwrap();
+fn test_encode(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let data = &*fixture.data;
 
-    let Decoder { key, comment, meta, image, .. } = Decoder::decode(reader).unwrap();
+    let encoded = ncm_meta::Encoder::encode(Decoder::decode(data).unwrap()).unwrap();
+    let tags = Tags::read(&encoded.data);
+    let audio = payload(&encoded.data);
+    assert_eq!(audio, payload(&fixture.audio));
 
-    if !meta.is_empty() {
-        let meta = String::from_utf8_lossy(&meta);
-        eprintln!("{meta}");
+    let options = DecodeOptions::default();
+    let others = [
+        ncm_meta::Encoder::encode_view(&Decoder::from_slice(data).unwrap()).unwrap(),
+        ncm_meta::Encoder::encode_stream(Registry::default().open_slice(data, &options).unwrap())
+            .unwrap(),
+        ncm_meta::Encoder::encode_plain(
+            fixture.audio.clone(),
+            &fixture.comment,
+            fixture.meta_kind,
+            &fixture.meta,
+            fixture.image.clone(),
+        )
+        .unwrap(),
+    ];
+    for other in others {
+        assert_eq!(Tags::read(&other.data), tags);
+        assert_eq!(payload(&other.data), audio);
+        assert_eq!(other.meta, encoded.meta);
+        assert_eq!(other.format, encoded.format);
+    }
+}
+
+#[testing::fixture("tests/input/*.ncm")]
+fn test_dj(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let ext = fixture.audio_type.to_string();
+
+    let main_music = format!(
+        r#"{{"musicId":1,"musicName":"main title","artist":[["main artist",2],["feat",3]],"album":"main album","albumPic":"","format":"{ext}"}}"#
+    );
+    let meta = format!(
+        r#"{{"programName":"program","djName":"dj","radioName":"radio","mainMusic":{main_music}}}"#
+    );
+    let encoder = Encoder {
+        key: &fixture.key,
+        meta_kind: MetaKind::Dj,
+        meta: meta.as_bytes(),
+        image: fixture.image_data(),
+        keys: KeySet::default(),
     };
 
-    let key_path = input.with_extension("key");
-    let expected_key = fs::read(key_path).unwrap();
-    assert_eq!(key, expected_key);
+    let mut packed = vec![];
+    encoder.encode(&*fixture.audio, &mut packed).unwrap();
+
+    let decoder = Decoder::decode(&*packed).unwrap();
+    assert_eq!(decoder.meta_kind, MetaKind::Dj);
+    assert_eq!(decoder.meta, meta.as_bytes());
+
+    let encoded = ncm_meta::Encoder::encode(decoder).unwrap();
+    assert_eq!(
+        Tags::read(&encoded.data),
+        Tags {
+            title: Some("main title".into()),
+            album: Some("main album".into()),
+            artist: Some("main artist/feat".into()),
+        }
+    );
+}
+
+#[testing::fixture("tests/input/*.ncm")]
+fn test_format(input: PathBuf) {
+    let data = fs::read(&input).unwrap();
+    let view = Decoder::from_slice(&data).unwrap();
+
+    let ncm_meta::Encoder { format, .. } = ncm_meta::Encoder::encode_view(&view).unwrap();
+    assert_eq!(format, ncm_meta::Format::new(view.audio_type(), view.meta_kind, &view.meta));
+    assert_eq!(format.sniffed, view.audio_type());
+    assert!(!format.is_mismatch(), "{format:?}");
+    assert_eq!(format.ext(), view.ext());
+
+    // a payload which is not recognised takes the extension from the meta
+    let unknown = ncm_meta::Format { sniffed: AudioType::Unknown, ..format };
+    assert_eq!(unknown.resolved(), format.declared);
+}
 
-    if !comment.is_empty() {
-        let comment_path = input.with_extension("comment");
-        let expected_comment = fs::read(comment_path).unwrap();
-        assert_eq!(comment, expected_comment);
+#[testing::fixture("tests/input/*.ncm")]
+fn test_qmc(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+
+    let mut data = fixture.audio.clone();
+    qmc::apply(0, &mut data);
+
+    let stream = Registry::default().open_slice(&data, &DecodeOptions::default()).unwrap();
+    let encoded = ncm_meta::Encoder::encode_stream(stream).unwrap();
+    assert_eq!(encoded.format.resolved(), fixture.audio_type);
+    assert_eq!(encoded.data, fixture.audio);
+}
+
+#[testing::fixture("tests/input/*.ncm")]
+fn test_plain_retag(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(input.file_stem().unwrap());
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // the folder holds what dump mode writes: the audio and the key, comment, meta and cover
+    let ncm = dir.join("song.ncm");
+    fs::copy(&input, &ncm).unwrap();
+    ncmc([Path::new("--dump"), &ncm]);
+    fs::remove_file(&ncm).unwrap();
+
+    let inputs = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path());
+    let Output { stderr, .. } =
+        ncmc(["--plain", "retag"].map(PathBuf::from).into_iter().chain(inputs));
+    let stderr = String::from_utf8_lossy(&stderr);
+    assert!(stderr.contains(&format!("skipping {}", dir.join("song.key").display())), "{stderr}");
+
+    let retagged = fs::read(dir.join(format!("song.tagged.{}", fixture.audio_type))).unwrap();
+    let encoded = ncm_meta::Encoder::encode(Decoder::decode(&*fixture.data).unwrap()).unwrap();
+    assert_eq!(Tags::read(&retagged), Tags::read(&encoded.data));
+    assert_eq!(payload(&retagged), payload(&encoded.data));
+}
+
+#[testing::fixture("tests/input/*.ncm")]
+fn test_lenient_without_audio(input: PathBuf) {
+    let fixture = Fixture::load(&input);
+    let dir =
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join("lenient").join(input.file_stem().unwrap());
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // cut off in the image, lenient mode keeps the meta but there is no audio to write
+    let ncm = dir.join("cut.ncm");
+    fs::write(&ncm, &fixture.data[..fixture.audio_offset as usize - 10]).unwrap();
+
+    for mode in [["--lenient"].as_slice(), &["--dump", "--lenient"]] {
+        let Output { stderr, .. } = ncmc(mode.iter().map(Path::new).chain([&*ncm]));
+        let stderr = String::from_utf8_lossy(&stderr);
+        assert!(stderr.contains("no audio was written"), "{mode:?}: {stderr}");
+        assert!(!dir.join(format!("cut.{}", fixture.audio_type)).exists(), "{mode:?}");
     }
+}
+
+/// Runs the ncmc binary with `args`, failing the test if it fails.
+fn ncmc(args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_ncmc")).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
 
-    if !meta.is_empty() {
-        let meta_path = input.with_extension("json");
-        let expected_meta = fs::read(meta_path).unwrap();
-        assert_eq!(meta, expected_meta);
+/// The title, album and artist tags of a converted file, several artists joined with `/`.
+#[derive(Debug, PartialEq)]
+struct Tags {
+    title: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+}
+
+impl Tags {
+    fn read(data: &[u8]) -> Self {
+        match AudioType::sniff(data) {
+            AudioType::Flac => {
+                let tag = metaflac::Tag::read_from(&mut io::Cursor::new(data)).unwrap();
+                let comments = tag.vorbis_comments().unwrap();
+                Self {
+                    title: comments.title().map(|title| title.join("/")),
+                    album: comments.album().map(|album| album.join("/")),
+                    artist: comments.artist().map(|artist| artist.join("/")),
+                }
+            }
+            AudioType::Mp3 => {
+                use id3::TagLike;
+
+                let tag = id3::Tag::read_from2(io::Cursor::new(data)).unwrap();
+                Self {
+                    title: tag.title().map(Into::into),
+                    album: tag.album().map(Into::into),
+                    artist: tag.artist().map(Into::into),
+                }
+            }
+            audio_type => panic!("no tags in {audio_type}"),
+        }
     }
+}
 
-    if let Some(image) = image {
-        let image_path = input.with_extension(image.ext());
-        let expected_image = fs::read(image_path).unwrap();
-        assert_eq!(image.data(), &expected_image);
+/// The audio of a converted file without the tags in front of it.
+fn payload(data: &[u8]) -> Vec<u8> {
+    let mut reader = io::Cursor::new(data);
+    match AudioType::sniff(data) {
+        AudioType::Flac => metaflac::Tag::skip_metadata(&mut reader),
+        AudioType::Mp3 => {
+            id3::Tag::skip(&mut reader).unwrap();
+            data[reader.position() as usize..].to_vec()
+        }
+        audio_type => panic!("no tags in {audio_type}"),
     }
 }
@@ -1,13 +1,38 @@
-use js_sys::Uint8Array;
-use ncm_core::decoder::Decoder;
+use js_sys::{Reflect, Uint8Array};
+use ncm_core::{format::Registry, options::DecodeOptions, Error};
 use ncm_meta::Encoder;
 
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-pub fn convert(input: &[u8]) -> Result<Uint8Array, String> {
-    let reader = std::io::Cursor::new(input);
-    let decoder = Decoder::decode(reader).map_err(|e| e.to_string())?;
-    let Encoder { data, .. } = Encoder::encode(decoder).map_err(|e| e.to_string())?;
+pub fn convert(input: &[u8]) -> Result<Uint8Array, JsValue> {
+    let stream =
+        Registry::default().open_slice(input, &DecodeOptions::default()).map_err(decode_error)?;
+    let Encoder { data, .. } =
+        Encoder::encode_stream(stream).map_err(|e| js_sys::Error::new(&e.to_string()))?;
     Ok((&*data).into())
 }
+
+/// Converts a decode error into a JS `Error` with `kind` and `offset` properties.
+fn decode_error(error: Error) -> JsValue {
+    let kind = match &error {
+        Error::HeaderMismatch { .. } => "HeaderMismatch",
+        Error::UnsupportedFormat { .. } => "UnsupportedFormat",
+        Error::InvalidKey { .. } => "InvalidKey",
+        Error::InvalidComment { .. } => "InvalidComment",
+        Error::Base64 { .. } => "Base64",
+        Error::Padding { .. } => "Padding",
+        Error::InvalidMeta { .. } => "InvalidMeta",
+        Error::CrcMismatch { .. } => "CrcMismatch",
+        Error::FrameTooLarge { .. } => "FrameTooLarge",
+        Error::UnexpectedEof { .. } => "UnexpectedEof",
+        Error::Source { .. } => "Source",
+        Error::Io { .. } => "Io",
+        _ => "Unknown",
+    };
+
+    let js_error = js_sys::Error::new(&error.to_string());
+    let _ = Reflect::set(&js_error, &"kind".into(), &kind.into());
+    let _ = Reflect::set(&js_error, &"offset".into(), &(error.offset() as f64).into());
+    js_error.into()
+}